@@ -1,26 +1,65 @@
 mod utils;
 
 use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
-use clap::{error::ErrorKind, CommandFactory, Parser};
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
 use lazy_static::lazy_static;
 use local_ip_address::local_ip;
 use rand::Rng;
 use serde_json::{json, Value as JsonValue};
 use serde_urlencoded::from_str;
 use serde_yaml::Value as YamlValue;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 use utils::{
+    cache::CacheOptions,
+    cidr::CidrOptions,
     clash::{add_clash_template, build_clash_json},
-    file_data::MyData,
+    dns::{resolve_domains, ResolveMode, SharedResolver},
+    encode::Encoding,
+    error::SubError,
+    file_data::{parse_metric, MyData},
+    filter::{sort_by_metric, LocationFilter, MetricFilter, SortBy},
     indent::adjust_yaml_indentation,
+    mobile::{build_mobile_line, MobileClient},
+    quanx::{add_quanx_template, build_quanx_line},
+    quota::{QuotaCharge, QuotaStore},
+    rate_limit::RateLimiter,
+    reload::Watched,
     singbox::{add_singbox_template, build_singbox_json},
-    toml::Config,
+    sources::{check_source, merge_sources, parse_sources},
+    strategy::{default_seed, NodePicker, Strategy},
+    sub_headers::{build_subscription_headers, surge_managed_config},
+    surge::{add_surge_template, build_surge_line},
+    toml::{fits_port, usable_nodes, Config, SelectedNode, Subscription},
+    users::{
+        entitled_proxies, expire_text, expire_timestamp, find_user, parse_rules,
+        with_default_params,
+    },
     v2ray::build_v2ray_link,
+    validate::{load_config, validate_config},
+    xray::{add_xray_template, build_xray_json, default_xray_template},
 };
 
 const SPECIFICATION: &str = include_str!("../使用说明.txt");
 const CONFIG_FILE_PATH: &str = "config.toml";
 const SINGBOX_TEMPLATE_PATH: &str = "template/sing-box.json";
 const CLASH_TEMPLATE_PATH: &str = "template/clash.yaml";
+const SURGE_TEMPLATE_PATH: &str = "template/surge.conf";
+const QUANX_TEMPLATE_PATH: &str = "template/quanx.conf";
+const XRAY_TEMPLATE_PATH: &str = "template/xray.json";
+const QUOTA_FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5); // 多久保存一次请求次数
+                                                                                     // 支持转换的目标
+const TARGETS: [&str; 9] = [
+    "v2ray",
+    "singbox",
+    "clash",
+    "surge",
+    "surfboard",
+    "quanx",
+    "loon",
+    "shadowrocket",
+    "xray",
+];
 
 lazy_static! {
     static ref HTTP_PORTS: [u16; 7] = [80, 8080, 8880, 2052, 2082, 2086, 2095];
@@ -48,8 +87,16 @@ pub struct Params {
     pub enable_template: bool,
     pub proxy_type: String,
     pub tls_mode: bool,
-    pub data_source: String,
+    pub data_sources: Vec<String>,
     pub page: usize,
+    pub encode: String,
+    pub cidr: String,
+    pub seed: Option<u64>,
+    pub metric_filter: MetricFilter,
+    pub location_filter: LocationFilter,
+    pub sort: String,
+    pub resolve: Option<ResolveMode>,
+    pub strategy: Strategy,
 }
 
 /// 基于HTTP传输协议的vless、trojan、ss-v2ray代理转换v2ray、sing-box、clash订阅工具
@@ -60,13 +107,118 @@ struct Args {
     #[arg(short, long, default_value = "10222")]
     port: u16,
 
-    /// 默认转换为v2ray，可选singbox、clash
+    /// 默认转换为v2ray，可选singbox、clash、surge、surfboard、quanx、loon、shadowrocket、xray
     #[arg(long, default_value = "v2ray")]
     target: String,
+
+    /// v2ray、shadowrocket订阅的默认编码，可选plain、base64、base64-nopad、base64url、base64url-nopad
+    #[arg(long, default_value = "base64")]
+    encode: String,
+
+    /// 检查config.toml和模板文件是否修改的间隔(秒)，修改后自动重新加载，0表示不检查
+    #[arg(long, default_value = "2")]
+    reload_interval: u64,
+
+    /// 网络数据源的缓存有效期(秒)，过期后向服务器确认是否有更新，获取失败时继续使用过期的缓存
+    #[arg(long, default_value = "300")]
+    cache_ttl: u64,
+
+    /// 网络数据源的缓存同时保存到这个文件夹中，重启后还能使用，不设置就只缓存在内存中
+    #[arg(long)]
+    cache_dir: Option<String>,
+
+    /// 保存每个token每天请求次数的文件，重启后继续计数
+    #[arg(long, default_value = "quota.json")]
+    quota_file: String,
+
+    /// 最多同时处理几个订阅转换，超出时返回429，0表示不限制
+    #[arg(long, default_value = "8")]
+    max_concurrent: usize,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Commands {
+    /// 检查toml配置文件，打印发现的所有问题
+    Check {
+        /// 要检查的配置文件路径
+        #[arg(short, long, default_value = CONFIG_FILE_PATH)]
+        config: String,
+    },
 }
 
 struct AppState {
     args: Args,
+    cache_options: CacheOptions,
+    quota: QuotaStore,
+    ip_limiter: RateLimiter,
+    token_limiter: RateLimiter,
+    conversions: Option<Arc<Semaphore>>, // 限制同时处理的订阅转换数量
+    resolver: SharedResolver,            // 解析域名使用的解析器，所有请求共用
+    config: Watched<Config>,
+    singbox_template: Watched<JsonValue>,
+    clash_template: Watched<YamlValue>,
+    surge_template: Watched<String>,
+    quanx_template: Watched<String>,
+    xray_template: Watched<JsonValue>,
+}
+
+impl AppState {
+    fn new(args: Args) -> Self {
+        AppState {
+            cache_options: CacheOptions {
+                ttl: std::time::Duration::from_secs(args.cache_ttl),
+                dir: args.cache_dir.as_ref().map(std::path::PathBuf::from),
+            },
+            quota: QuotaStore::open(Some(std::path::PathBuf::from(&args.quota_file))),
+            ip_limiter: RateLimiter::default(),
+            token_limiter: RateLimiter::default(),
+            conversions: match args.max_concurrent {
+                0 => None,
+                n => Some(Arc::new(Semaphore::new(n))),
+            },
+            resolver: SharedResolver::default(),
+            args,
+            config: Watched::new(CONFIG_FILE_PATH, parse_config),
+            singbox_template: Watched::new(SINGBOX_TEMPLATE_PATH, |content| {
+                serde_json::from_str(content).map_err(|e| e.to_string())
+            }),
+            clash_template: Watched::new(CLASH_TEMPLATE_PATH, |content| {
+                serde_yaml::from_str(content).map_err(|e| e.to_string())
+            }),
+            surge_template: Watched::new(SURGE_TEMPLATE_PATH, |content| Ok(content.to_string())),
+            quanx_template: Watched::new(QUANX_TEMPLATE_PATH, |content| Ok(content.to_string())),
+            xray_template: Watched::new(XRAY_TEMPLATE_PATH, |content| {
+                serde_json::from_str(content).map_err(|e| e.to_string())
+            }),
+        }
+    }
+
+    // 检查配置文件和模板是否有修改，有修改就重新加载
+    fn reload_if_changed(&self) {
+        self.config.reload_if_changed();
+        self.singbox_template.reload_if_changed();
+        self.clash_template.reload_if_changed();
+        self.surge_template.reload_if_changed();
+        self.quanx_template.reload_if_changed();
+        self.xray_template.reload_if_changed();
+    }
+}
+
+// 解析toml配置，并检查配置中的问题，有问题就不使用它
+fn parse_config(content: &str) -> Result<Config, String> {
+    let config: Config = toml::from_str(content).map_err(|e| e.to_string())?;
+    let problems = validate_config(&config, &HTTP_PORTS, &HTTPS_PORTS);
+    match problems.is_empty() {
+        true => Ok(config),
+        false => Err(problems
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect::<Vec<_>>()
+            .join("; ")),
+    }
 }
 
 #[get("/")]
@@ -75,8 +227,10 @@ async fn index(req: HttpRequest) -> impl Responder {
 
     let html_doc = SPECIFICATION.replace("127.0.0.1:10222", &host_address);
 
-    // 获取当前局域网IP地址
-    let ip_address = local_ip().unwrap().to_string();
+    // 获取当前局域网IP地址，获取不到就保持原样
+    let ip_address = local_ip()
+        .map(|ip| ip.to_string())
+        .unwrap_or("127.0.0.1".to_string());
 
     // 获取当前URL
     let url = format!(
@@ -96,6 +250,27 @@ async fn index(req: HttpRequest) -> impl Responder {
         .body(html_content)
 }
 
+// 解析数值类型的URI参数，如：maxlatency=200、maxloss=0.1、maxloss=10%、minspeed=5
+fn parse_number(key: &str, value: &str) -> Result<f64, SubError> {
+    parse_metric(value)
+        .ok_or_else(|| SubError::BadRequest(format!("{}的值只能是数字，不能是：{}", key, value)))
+}
+
+// 解析整数参数，不是整数(或者超出范围)时返回400
+fn parse_integer<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, SubError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| SubError::BadRequest(format!("{}的值只能是整数，不能是：{}", key, value)))
+}
+
+// 当前的Unix时间戳(秒)
+fn now_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
 fn string_to_bool(value: &str, current_bool_value: bool) -> bool {
     match value {
         "true" | "1" => true,
@@ -105,23 +280,152 @@ fn string_to_bool(value: &str, current_bool_value: bool) -> bool {
 }
 
 #[get("/sub")]
-async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+async fn subconverter(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, SubError> {
+    build_subscription(req, data, None).await
+}
+
+// token也可以放在路径中，如：/sub/{token}?target=clash
+#[get("/sub/{token}")]
+async fn subconverter_with_token(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, SubError> {
+    build_subscription(req, data, Some(path.into_inner())).await
+}
+
+async fn build_subscription(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path_token: Option<String>,
+) -> Result<HttpResponse, SubError> {
     let query_str = req.query_string();
-    let params: Vec<(String, String)> = from_str(&query_str).expect("Failed to parse query string");
+    let params: Vec<(String, String)> = from_str(query_str)
+        .map_err(|e| SubError::BadRequest(format!("无法解析查询字符串：{}", e)))?;
+
+    // ———————————————————————————————— 读取toml配置 ————————————————————————————————
+
+    // 启动时已经检查过配置，这里使用最后一次加载成功的配置
+    let toml_value = data
+        .config
+        .load()
+        .map_err(SubError::Config)?
+        .ok_or_else(|| SubError::Config(format!("{} 不存在", CONFIG_FILE_PATH)))?;
+
+    // ———————————————————————————————— 限制请求速度 ————————————————————————————————
+
+    // 按客户端的IP(连接的地址，不使用X-Forwarded-For，避免伪造)限制请求速度
+    let limits = &toml_value.limits;
+    let client_ip = req.peer_addr().map(|addr| addr.ip().to_string());
+    data.ip_limiter
+        .check(
+            client_ip.as_deref().unwrap_or_default(),
+            limits.ip_per_minute,
+            limits.ip_burst,
+        )
+        .map_err(|retry_after| SubError::TooManyRequests {
+            message: "请求太频繁，请稍后再试".to_string(),
+            retry_after,
+        })?;
+
+    // 同时处理的订阅转换太多时，直接拒绝，permit在函数返回时释放
+    let _permit = match &data.conversions {
+        Some(conversions) => Some(conversions.clone().try_acquire_owned().map_err(|_| {
+            SubError::TooManyRequests {
+                message: "服务器正在处理的订阅转换太多，请稍后再试".to_string(),
+                retry_after: 1,
+            }
+        })?),
+        None => None,
+    };
+
+    // ———————————————————————————————— 检查用户的token ———————————————————————————————
+
+    // 配置了[[users]]时，只能使用token对应用户的节点，没有配置时可以使用全部节点
+    let token = path_token.or_else(|| {
+        params
+            .iter()
+            .find(|(key, _)| key.to_lowercase() == "token")
+            .map(|(_, value)| value.clone())
+    });
+    let user = match toml_value.users.is_empty() {
+        true => None,
+        false => Some(
+            token
+                .as_deref()
+                .and_then(|token| find_user(&toml_value.users, token))
+                .ok_or_else(|| SubError::Forbidden("缺少token或token无效".to_string()))?,
+        ),
+    };
+    // 订阅信息，用户有到期时间时，放到subscription-userinfo响应头中
+    let mut subscription = toml_value.subscription.clone();
+    // 今天的请求次数，生成订阅失败(返回错误)时会自动退回
+    let (params, proxies, quota_charge) = match user {
+        None => (params, toml_value.proxies.clone(), None),
+        Some(user) => {
+            data.token_limiter
+                .check(&user.token, limits.token_per_minute, limits.token_burst)
+                .map_err(|retry_after| SubError::TooManyRequests {
+                    message: "这个token的请求太频繁，请稍后再试".to_string(),
+                    retry_after,
+                })?;
+            let expire = expire_timestamp(user).map_err(SubError::Config)?;
+            if let Some(expire) = expire {
+                if now_timestamp() >= expire {
+                    return Err(SubError::Forbidden(format!(
+                        "订阅已过期，到期时间：{}",
+                        expire_text(user)
+                    )));
+                }
+                subscription.expire = Some(expire);
+            }
+            let quota_charge = match user.max_requests_per_day {
+                Some(limit) => Some(
+                    QuotaCharge::acquire(&data.quota, &user.token, limit).map_err(
+                        |retry_after| SubError::TooManyRequests {
+                            message: format!(
+                                "今天的请求次数已用完，每天最多{}次，请明天再试",
+                                limit
+                            ),
+                            retry_after,
+                        },
+                    )?,
+                ),
+                None => None,
+            };
+            let rules = parse_rules(user).map_err(SubError::Config)?;
+            (
+                with_default_params(user, params),
+                entitled_proxies(&toml_value.proxies, &rules),
+                quota_charge,
+            )
+        }
+    };
 
     // ———————————————————————————————— URI参数控制 —————————————————————————————————
 
     let mut uri_params = Params {
-        target: data.args.target.to_string(), // 转换的目标，只支持v2ray、singbox、clash
+        target: data.args.target.to_string(), // 转换的目标，只支持v2ray、singbox、clash、surge、surfboard、quanx、loon、shadowrocket、xray
         tls_mode: true,                       // 用于筛选csv数据中的TLS/非TLS端口
-        enable_template: true,                // 是否启用sing-box、clash配置模板
+        enable_template: true,                // 是否启用sing-box、clash、surge、quanx、xray配置模板
         default_port: 0,                      // 0表示：由内部代码确定端口
         max_node_count: 300,
-        page: 1,                    // 默认使用第一页的数据，构建节点订阅
-        userid: 0,                  // 使用toml配置中，具体哪个代理信息，有效值从1开始
-        proxy_type: "".to_string(), // 使用toml配置中，哪些代理信息，可选：[vless,trojan]
-        data_source: "./data".to_string(),
+        page: 1,                                    // 默认使用第一页的数据，构建节点订阅
+        userid: 0,                       // 使用toml配置中，具体哪个代理信息，有效值从1开始
+        proxy_type: "".to_string(),      // 使用toml配置中，哪些代理信息，可选：[vless,trojan]
+        data_sources: Vec::new(), // 可以有多个source参数，没有就使用config.toml中的默认数据源
         column_name: "colo".to_string(), // csv文件中，以哪个列的字段名作为前缀？可选：[colo,loc,region,city]
+        encode: data.args.encode.to_string(), // v2ray、shadowrocket订阅的编码方式
+        cidr: "".to_string(), // txt文件中CIDR网段的展开方式，可选：[数字N,all]，为空时不展开
+        seed: None, // 随机数种子(挑选节点、端口、指纹，展开CIDR网段)，相同的种子生成的订阅完全一样
+        metric_filter: MetricFilter::default(), // 根据csv文件中的延迟、丢包率、下载速度过滤数据
+        location_filter: LocationFilter::default(), // 根据数据中心、国家代码、地区、城市过滤数据
+        sort: "".to_string(), // 按测速结果排序，可选：[latency,speed,loss]，为空时不排序
+        resolve: None, // 域名的处理方式，可选：[keep,replace,drop]，为空时不处理
+        strategy: Strategy::default(), // IP/域名分配节点配置的方式，可选：[random,round_robin,hash,cross]
     };
 
     // ———————————————————————————————— 解析URI参数 —————————————————————————————————
@@ -131,61 +435,164 @@ async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Respo
             uri_params.target = value.to_string();
         } else if key.to_lowercase() == "template" {
             uri_params.enable_template = string_to_bool(&value, uri_params.enable_template);
-        } else if vec!["tls", "mode", "tls_mode"].contains(&key.to_lowercase().as_str()) {
+        } else if ["tls", "mode", "tls_mode"].contains(&key.to_lowercase().as_str()) {
             uri_params.tls_mode = string_to_bool(&value, uri_params.tls_mode);
-        } else if vec!["type", "proxy", "proxytype"].contains(&key.to_lowercase().as_str()) {
+        } else if ["type", "proxy", "proxytype"].contains(&key.to_lowercase().as_str()) {
             uri_params.proxy_type = value.to_string();
-        } else if vec!["source", "datasource"].contains(&key.to_lowercase().as_str()) {
-            uri_params.data_source = value.to_string();
-        } else if vec!["column", "columnname"].contains(&key.to_lowercase().as_str()) {
+        } else if ["source", "datasource"].contains(&key.to_lowercase().as_str()) {
+            uri_params.data_sources.push(value.to_string());
+        } else if ["column", "columnname"].contains(&key.to_lowercase().as_str()) {
             uri_params.column_name = value.to_string();
-        } else if vec!["n", "nodesize", "nodecount"].contains(&key.to_lowercase().as_str()) {
-            uri_params.max_node_count = value.parse().unwrap_or(uri_params.max_node_count);
+        } else if ["n", "nodesize", "nodecount"].contains(&key.to_lowercase().as_str()) {
+            uri_params.max_node_count = parse_integer(&key, &value)?;
+        } else if ["encode", "encoding"].contains(&key.to_lowercase().as_str()) {
+            uri_params.encode = value.to_string();
+        } else if key.to_lowercase() == "cidr" {
+            uri_params.cidr = value.to_string();
+        } else if key.to_lowercase() == "seed" {
+            let seed = value.parse().map_err(|_| {
+                SubError::BadRequest(format!("seed只能是非负整数，不能是：{}", value))
+            })?;
+            uri_params.seed = Some(seed);
+        } else if ["maxlatency", "max_latency"].contains(&key.to_lowercase().as_str()) {
+            uri_params.metric_filter.max_latency = Some(parse_number(&key, &value)?);
+        } else if ["maxloss", "max_loss"].contains(&key.to_lowercase().as_str()) {
+            uri_params.metric_filter.max_loss = Some(parse_number(&key, &value)?);
+        } else if ["minspeed", "min_speed"].contains(&key.to_lowercase().as_str()) {
+            uri_params.metric_filter.min_speed = Some(parse_number(&key, &value)?);
+        } else if ["colo", "loc", "region", "city"].contains(&key.to_lowercase().as_str()) {
+            let filter = &mut uri_params.location_filter;
+            let field_filter = match key.to_lowercase().as_str() {
+                "colo" => &mut filter.colo,
+                "loc" => &mut filter.loc,
+                "region" => &mut filter.region,
+                _ => &mut filter.city,
+            };
+            field_filter
+                .add(&value)
+                .map_err(|e| SubError::BadRequest(format!("{}：{}", key, e)))?;
+        } else if key.to_lowercase() == "resolve" {
+            uri_params.resolve = Some(ResolveMode::parse(&value).map_err(SubError::BadRequest)?);
+        } else if key.to_lowercase() == "strategy" {
+            uri_params.strategy = Strategy::parse(&value).map_err(SubError::BadRequest)?;
+        } else if key.to_lowercase() == "sort" {
+            uri_params.sort = value.to_string();
         } else if key.to_lowercase() == "page" {
-            uri_params.page = value.parse().unwrap_or(uri_params.page).max(1);
-        } else if vec!["id", "userid"].contains(&key.to_lowercase().as_str()) {
-            uri_params.userid = value.parse().unwrap_or(uri_params.userid);
-        } else if vec!["dport", "defaultport"].contains(&key.to_lowercase().as_str()) {
-            let port = value.parse().unwrap_or(uri_params.default_port);
-            uri_params.default_port = (80..=65535)
-                .contains(&port)
-                .then_some(port)
-                .unwrap_or(uri_params.default_port);
+            uri_params.page = parse_integer::<usize>(&key, &value)?.max(1);
+        } else if ["id", "userid"].contains(&key.to_lowercase().as_str()) {
+            uri_params.userid = parse_integer(&key, &value)?;
+        } else if ["dport", "defaultport"].contains(&key.to_lowercase().as_str()) {
+            let port = parse_integer(&key, &value)?;
+            // 0表示由内部代码确定端口
+            if port != 0 && !(80..=65535).contains(&port) {
+                return Err(SubError::BadRequest(format!(
+                    "{}的值只能是80~65535，不能是：{}",
+                    key, value
+                )));
+            }
+            uri_params.default_port = port;
         }
     }
 
-    // ———————————————————————————————— 读取toml配置 ————————————————————————————————
+    let sort_by = match uri_params.sort.is_empty() {
+        true => None,
+        false => Some(SortBy::parse(&uri_params.sort).map_err(SubError::BadRequest)?),
+    };
 
-    let toml_content = std::fs::read_to_string(CONFIG_FILE_PATH).unwrap();
-    let toml_value: Config = toml::from_str(&toml_content).expect("Failed to parse TOML");
+    let encoding = Encoding::parse(&uri_params.encode).map_err(SubError::BadRequest)?;
+
+    // 每页至少一个节点，否则无法分页
+    if uri_params.max_node_count == 0 {
+        return Err(SubError::BadRequest("n必须大于0".to_string()));
+    }
+
+    if !TARGETS.contains(&uri_params.target.as_str()) {
+        return Err(SubError::BadRequest(format!(
+            "不支持的target：{}，可选：{}",
+            uri_params.target,
+            TARGETS.join("、")
+        )));
+    }
+
+    // 随机数种子，没有seed参数时使用token和查询字符串的哈希值，相同的请求生成的订阅完全一样
+    let seed = uri_params
+        .seed
+        .unwrap_or_else(|| default_seed(token.as_deref(), query_str));
+    let mut picker = NodePicker::new(uri_params.strategy, seed);
 
     // ——————————————————— 读取IP/Domain数据(填到节点的服务器地址) ————————————————————
 
-    // 针对win11中"复制文件地址"出现双引号的情况
-    let trimmed_quotes_path = uri_params.data_source.trim_matches('"');
+    // 所有的数据源，每个source参数中可以用|分隔多个数据源，也可以使用config.toml中的简称
+    if uri_params.data_sources.is_empty() {
+        uri_params
+            .data_sources
+            .push(toml_value.sources.default.clone());
+    }
+    let sources = parse_sources(&uri_params.data_sources, &toml_value.sources.names)
+        .map_err(SubError::BadRequest)?
+        .into_iter()
+        // 只允许读取config.toml中配置的文件夹、链接
+        .map(|source| check_source(source, &toml_value.sources).map_err(SubError::Forbidden))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    // 从文件中读取数据，最大读取数，数据没有过滤
+    // 从文件中读取数据，最大读取数（所有数据源加起来），数据没有过滤
     let max_line: usize = 10000;
 
-    // 获取数据(网络数据/本地数据)
-    let my_datas: Vec<MyData> = if trimmed_quotes_path.to_lowercase().starts_with("https://") {
-        // 传入的一个https://链接，就从网络获取数据
-        utils::net_data::process_network_data(
-            &uri_params.column_name,
-            uri_params.default_port,
-            max_line,
-            trimmed_quotes_path,
-        )
-    } else {
-        // 传入的是本地文件路径，就从本地获取数据
-        utils::file_data::process_files_data(
-            &uri_params.column_name, // 获取指定字段的数据作为节点别名的前缀
-            uri_params.default_port, // 没有找到端口的情况，就使用它
-            max_line,                // 获取指定数量的数据就返回
-            trimmed_quotes_path,     // 指定数据源所在文件夹路径或文件路径
-        )
+    // CIDR网段的展开方式，没有设置就不展开（跳过txt文件中含有"/"的行）
+    let cidr_options = match uri_params.cidr.is_empty() {
+        true => None,
+        false => Some(
+            CidrOptions::parse(&uri_params.cidr, Some(seed), max_line)
+                .map_err(SubError::BadRequest)?,
+        ),
     };
 
+    // 获取每个数据源的数据(网络数据/本地数据)
+    let mut source_datas: Vec<(usize, Vec<MyData>)> = Vec::new();
+    let mut remaining = max_line; // 还可以读取多少条数据，避免重复的数据源(如：source=a|a|a)成倍地读取
+    for source in &sources {
+        if remaining == 0 {
+            break;
+        }
+        let count = source.limit.unwrap_or(remaining).min(remaining);
+        let datas = if source.is_remote() {
+            // 传入的是http://或https://链接，就从网络获取数据
+            utils::net_data::process_network_data(
+                &uri_params.column_name,
+                uri_params.default_port,
+                count,
+                &source.location,
+                cidr_options.as_ref(),
+                &data.cache_options,
+            )
+            .await
+            .map_err(SubError::Upstream)?
+        } else {
+            // 传入的是本地文件路径，就从本地获取数据
+            utils::file_data::process_files_data(
+                &uri_params.column_name, // 获取指定字段的数据作为节点别名的前缀
+                uri_params.default_port, // 没有找到端口的情况，就使用它
+                count,                   // 获取指定数量的数据就返回
+                &source.location,        // 指定数据源所在文件夹路径或文件路径
+                cidr_options.as_ref(),   // 是否展开CIDR网段
+            )
+            .map_err(SubError::BadRequest)?
+        };
+        remaining = remaining.saturating_sub(datas.len());
+        source_datas.push((source.weight, datas));
+    }
+    // 按权重合并所有数据源的数据，并去掉重复的地址
+    let mut my_datas: Vec<MyData> = merge_sources(source_datas);
+
+    // 解析数据中的域名，替换为IP或剔除解析不到IP的域名
+    if let Some(mode) = uri_params.resolve {
+        let resolver = data
+            .resolver
+            .get(&toml_value.dns)
+            .map_err(SubError::Config)?;
+        my_datas = resolve_domains(my_datas, mode, &resolver).await;
+    }
+
     // ———————————————————————————————— 过滤不要的数据 ——————————————————————————————
 
     // 根据TLS模式是否开启，反向剔除不要端口的数据
@@ -193,7 +600,7 @@ async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Respo
         true => HTTP_PORTS.to_vec(),   // 过滤掉非TLS模式的端口
         false => HTTPS_PORTS.to_vec(), // 过滤掉TLS模式的端口
     };
-    let filtered_data: Vec<MyData> = my_datas
+    let mut filtered_data: Vec<MyData> = my_datas
         .iter()
         .filter(|item| {
             // 端口不在filter_ports中，则保留
@@ -203,29 +610,72 @@ async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Respo
                 true // 如果port为None，保留该元素
             }
         })
+        .filter(|item| uri_params.metric_filter.matches(item)) // 剔除测速结果不满足条件的数据
+        .filter(|item| uri_params.location_filter.matches(item)) // 剔除不是指定位置的数据
         .cloned()
         .collect();
 
+    // 按测速结果排序，最好的排在前面
+    if let Some(sort_by) = sort_by {
+        sort_by_metric(&mut filtered_data, sort_by);
+    }
+
     // —————————————————————————————————— 数据分页 ——————————————————————————————————
 
-    // 定义每页的最大长度（元素个数），主要限制singbox、clash配置文件最多节点数
+    // 定义每页的最大长度（元素个数），主要限制singbox、clash、surge、quanx、xray配置文件最多节点数
     let page_size = match uri_params.target.as_str() {
-        "singbox" | "clash" => match (1..151).contains(&uri_params.max_node_count) {
-            true => uri_params.max_node_count,
-            false => 50,
-        },
+        "singbox" | "clash" | "surge" | "surfboard" | "quanx" | "xray" => {
+            match (1..151).contains(&uri_params.max_node_count) {
+                true => uri_params.max_node_count,
+                false => 50,
+            }
+        }
         _ => uri_params.max_node_count,
     };
 
-    // 将 Vec<MyData> 转换为 Vec<Vec<MyData>>
-    let paginated_data: Vec<Vec<MyData>> = filtered_data
+    // 可以使用的节点（剔除了目标客户端不支持的节点），所有地址共用
+    let is_supported: fn(&SelectedNode) -> bool = match uri_params.target.as_str() {
+        "surge" | "surfboard" => utils::surge::is_supported,
+        "xray" => utils::xray::is_supported,
+        "loon" | "shadowrocket" => MobileClient::from_target(&uri_params.target)
+            .unwrap()
+            .is_supported(),
+        _ => |_| true,
+    };
+    let nodes = usable_nodes(
+        &proxies,
+        &uri_params.proxy_type,
+        uri_params.userid,
+        is_supported,
+    );
+
+    // 每个地址跟端口匹配的节点组合：cross跟每个匹配的节点都组合一次，其它方式只组合一次，没有匹配的节点就跳过；
+    // 先组合再分页，每页的节点数才不会超过page_size
+    let uri_port = uri_params.default_port;
+    let assignments: Vec<(&MyData, usize)> = filtered_data
+        .iter()
+        .flat_map(|item| {
+            let csv_port = item.port.unwrap_or(0);
+            let fitting = nodes
+                .iter()
+                .filter(|node| fits_port(node, csv_port, uri_port, &HTTP_PORTS, &HTTPS_PORTS))
+                .count();
+            let rounds = match picker.strategy() {
+                Strategy::Cross => fitting,
+                _ => fitting.min(1),
+            };
+            (0..rounds).map(move |round| (item, round))
+        })
+        .collect();
+
+    // 将 Vec<(&MyData, usize)> 转换为 Vec<Vec<(&MyData, usize)>>
+    let paginated_data: Vec<Vec<(&MyData, usize)>> = assignments
         .chunks(page_size)
         .map(|chunk| chunk.to_vec())
         .collect();
 
     // —————————————————————————————————— 构建节点 ——————————————————————————————————
 
-    let uri_port = uri_params.default_port;
     // 收集构建的所有节点别名和v2ray/singbox/clash的节点信息
     let mut vec: Vec<(String, String)> = Vec::new();
     // 根据页码获取某一页的数据，默认为1，且页码要从1开始
@@ -233,28 +683,31 @@ async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Respo
         Some(page_data) => {
             // 检查是否读取到数据，如果为空就返回空白页面
             if page_data.is_empty() {
-                return HttpResponse::Ok()
-                    .content_type("text/plain; charset=utf-8")
-                    .body("".to_string());
+                return Ok(subscription_response(
+                    &subscription,
+                    &uri_params.target,
+                    quota_charge,
+                    "".to_string(),
+                ));
             }
-            for item in page_data {
+            for &(item, round) in page_data {
+                picker.start(round);
                 let csv_tag: String = item.alias.clone().unwrap_or("".to_string()); // 数据中心,地区,城市,国家代码
                 let csv_addr: String = item.addr.clone();
                 let csv_port: u16 = item.port.unwrap_or(0);
-                let fingerprint = FINGERPRINT[rand::thread_rng().gen_range(0..FINGERPRINT.len())]; // 指纹
+                let fingerprint = FINGERPRINT[picker.rng.gen_range(0..FINGERPRINT.len())]; // 指纹
                 match uri_params.target.as_str() {
                     "v2ray" => {
                         let link = build_v2ray_link(
-                            &toml_value.proxies,
+                            &nodes,
                             csv_tag,
                             csv_addr,
                             csv_port,
                             uri_port,
-                            uri_params.userid,
-                            uri_params.proxy_type.clone(),
                             fingerprint.to_string(),
                             &HTTP_PORTS,
                             &HTTPS_PORTS,
+                            &mut picker,
                         );
                         if !link.is_empty() {
                             vec.push(("".to_string(), link));
@@ -262,16 +715,15 @@ async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Respo
                     }
                     "singbox" => {
                         let (remark, singbox_json) = build_singbox_json(
-                            &toml_value.proxies,
+                            &nodes,
                             csv_tag,
                             csv_addr,
                             csv_port,
                             uri_port,
-                            uri_params.userid,
-                            uri_params.proxy_type.clone(),
                             fingerprint.to_string(),
                             &HTTP_PORTS,
                             &HTTPS_PORTS,
+                            &mut picker,
                         );
                         if !remark.is_empty() {
                             let formatted_json = serde_json::to_string_pretty(&singbox_json)
@@ -281,16 +733,15 @@ async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Respo
                     }
                     "clash" => {
                         let (remark, clash_json) = build_clash_json(
-                            &toml_value.proxies,
+                            &nodes,
                             csv_tag,
                             csv_addr,
                             csv_port,
                             uri_port,
-                            uri_params.userid,
-                            uri_params.proxy_type.clone(),
                             fingerprint.to_string(),
                             &HTTP_PORTS,
                             &HTTPS_PORTS,
+                            &mut picker,
                         );
                         if !remark.is_empty() {
                             let formatted_json = serde_json::to_string_pretty(&clash_json)
@@ -298,40 +749,115 @@ async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Respo
                             vec.push((remark, formatted_json));
                         }
                     }
+                    "surge" | "surfboard" => {
+                        let (remark, line) = build_surge_line(
+                            &nodes,
+                            csv_tag,
+                            csv_addr,
+                            csv_port,
+                            uri_port,
+                            &HTTP_PORTS,
+                            &HTTPS_PORTS,
+                            &mut picker,
+                        );
+                        if !remark.is_empty() {
+                            vec.push((remark, line));
+                        }
+                    }
+                    "quanx" => {
+                        let (remark, line) = build_quanx_line(
+                            &nodes,
+                            csv_tag,
+                            csv_addr,
+                            csv_port,
+                            uri_port,
+                            &HTTP_PORTS,
+                            &HTTPS_PORTS,
+                            &mut picker,
+                        );
+                        if !remark.is_empty() {
+                            vec.push((remark, line));
+                        }
+                    }
+                    "xray" => {
+                        let (remark, xray_json) = build_xray_json(
+                            &nodes,
+                            csv_tag,
+                            csv_addr,
+                            csv_port,
+                            uri_port,
+                            fingerprint.to_string(),
+                            &HTTP_PORTS,
+                            &HTTPS_PORTS,
+                            &mut picker,
+                        );
+                        if !remark.is_empty() {
+                            let formatted_json = serde_json::to_string_pretty(&xray_json)
+                                .unwrap_or_else(|_| "".to_string());
+                            vec.push((remark, formatted_json));
+                        }
+                    }
+                    "loon" | "shadowrocket" => {
+                        let (remark, line) = build_mobile_line(
+                            MobileClient::from_target(&uri_params.target).unwrap(),
+                            &nodes,
+                            csv_tag,
+                            csv_addr,
+                            csv_port,
+                            uri_port,
+                            fingerprint.to_string(),
+                            &HTTP_PORTS,
+                            &HTTPS_PORTS,
+                            &mut picker,
+                        );
+                        if !remark.is_empty() {
+                            vec.push((remark, line));
+                        }
+                    }
                     _ => {}
                 }
             }
         }
+        // 没有读取到数据，就返回空白页面，跟页码超出范围区分开
+        None if paginated_data.is_empty() => {
+            return Ok(subscription_response(
+                &subscription,
+                &uri_params.target,
+                quota_charge,
+                "".to_string(),
+            ));
+        }
         None => {
-            println!("无效的页码：{}", uri_params.page);
-            return HttpResponse::Ok()
-                .content_type("text/plain; charset=utf-8")
-                .body("".to_string());
+            return Err(SubError::PageNotFound {
+                page: uri_params.page,
+                pages: paginated_data.len(),
+            });
         }
     }
 
-    // --------------------------- 读取singbox、clash模板 ---------------------------
-
-    // sing-box配置模板
-    let template_content = std::fs::read_to_string(SINGBOX_TEMPLATE_PATH).unwrap();
-    let singbox_template: JsonValue = serde_json::from_str(&template_content).unwrap();
-
-    // clash配置模板
-    let template_content = std::fs::read_to_string(CLASH_TEMPLATE_PATH).unwrap();
-    let mut clash_template: YamlValue = serde_yaml::from_str(&template_content).unwrap();
-
     // ------------------------- 处理输出的内容(是否添加模板) -------------------------
 
     let html_body: String = match uri_params.target.as_str() {
-        "v2ray" => vec
+        "v2ray" | "shadowrocket" => {
+            let links = vec
+                .iter()
+                .map(|(_, value)| value.as_str())
+                .collect::<Vec<&str>>()
+                .join("\n");
+            encoding.encode(&links)
+        }
+        "loon" => vec
             .iter()
-            .map(|(_, value)| value)
-            .cloned()
-            .collect::<Vec<String>>()
+            .map(|(_, value)| value.as_str())
+            .collect::<Vec<&str>>()
             .join("\n"),
         "singbox" => {
             match uri_params.enable_template {
-                true => add_singbox_template(singbox_template, vec),
+                true => {
+                    let singbox_template =
+                        required_template(&data.singbox_template, SINGBOX_TEMPLATE_PATH)?;
+                    add_singbox_template((*singbox_template).clone(), vec)
+                }
                 false => {
                     let outbounds_json: JsonValue = json!({
                         "outbounds": vec.iter().map(|(_, v)| serde_json::from_str(v).unwrap_or(JsonValue::Null)).collect::<Vec<_>>()
@@ -344,6 +870,9 @@ async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Respo
         "clash" => {
             match uri_params.enable_template {
                 true => {
+                    let clash_template =
+                        required_template(&data.clash_template, CLASH_TEMPLATE_PATH)?;
+                    let mut clash_template = (*clash_template).clone();
                     add_clash_template(&mut clash_template, vec);
                     // 将serde_json::value数据转换为YAML字符串，并美化字符串的缩进形式
                     adjust_yaml_indentation(
@@ -361,11 +890,115 @@ async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Respo
                 }
             }
         }
+        "surge" | "surfboard" => {
+            // surge配置模板是可选的，文件不存在就只输出节点信息
+            // 不使用模板时不加载模板，模板文件有问题也不影响
+            let template = match uri_params.enable_template {
+                true => optional_template(&data.surge_template)?,
+                false => None,
+            };
+            match template {
+                Some(template) => {
+                    let content = add_surge_template(&template, vec);
+                    // 完整的配置文件，才添加托管配置的首行
+                    let url = format!(
+                        "{}://{}{}",
+                        req.connection_info().scheme(),
+                        req.connection_info().host(),
+                        req.uri()
+                    );
+                    match surge_managed_config(&subscription, &url) {
+                        Some(managed) => format!("{}\n{}", managed, content),
+                        None => content,
+                    }
+                }
+                None => add_surge_template("[Proxy]", vec),
+            }
+        }
+        "xray" => {
+            // xray配置模板是可选的，文件不存在或不使用模板时，使用内置的默认配置
+            // 不使用模板时不加载模板，模板文件有问题也不影响
+            let template = match uri_params.enable_template {
+                true => optional_template(&data.xray_template)?,
+                false => None,
+            };
+            match template {
+                Some(template) => add_xray_template((*template).clone(), vec),
+                None => add_xray_template(default_xray_template(), vec),
+            }
+        }
+        "quanx" => {
+            // quanx配置模板是可选的，不使用模板时，只输出节点信息（可作为[server_remote]的订阅资源）
+            // 不使用模板时不加载模板，模板文件有问题也不影响
+            let template = match uri_params.enable_template {
+                true => optional_template(&data.quanx_template)?,
+                false => None,
+            };
+            match template {
+                Some(template) => add_quanx_template(&template, vec),
+                _ => vec
+                    .iter()
+                    .map(|(_, value)| value.as_str())
+                    .collect::<Vec<&str>>()
+                    .join("\n"),
+            }
+        }
         _ => "".to_string(),
     };
-    HttpResponse::Ok()
-        .content_type("text/plain; charset=utf-8")
-        .body(html_body)
+    Ok(subscription_response(
+        &subscription,
+        &uri_params.target,
+        quota_charge,
+        html_body,
+    ))
+}
+
+// 订阅生成成功的响应（包括没有数据的空白页面）：添加订阅信息的响应头，计入今天的请求次数
+fn subscription_response(
+    subscription: &Subscription,
+    target: &str,
+    quota_charge: Option<QuotaCharge<'_>>,
+    body: String,
+) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    response.content_type("text/plain; charset=utf-8");
+    // 订阅信息的响应头（订阅名称、更新间隔、流量信息等）
+    for header in build_subscription_headers(subscription, target) {
+        response.insert_header(header);
+    }
+    if let Some(charge) = quota_charge {
+        charge.commit();
+    }
+    response.body(body)
+}
+
+// 必须要有的模板(sing-box、clash)，没有加载成功就返回错误
+fn required_template<T>(template: &Watched<T>, path: &str) -> Result<Arc<T>, SubError> {
+    template
+        .load()
+        .map_err(SubError::Template)?
+        .ok_or_else(|| SubError::Template(format!("{} 不存在", path)))
+}
+
+// 可选的模板(surge、quanx、xray)，文件不存在返回None，文件有问题就返回错误
+fn optional_template<T>(template: &Watched<T>) -> Result<Option<Arc<T>>, SubError> {
+    template.load().map_err(SubError::Template)
+}
+
+// 检查toml配置文件，打印所有问题，没有问题返回true
+fn check_config(path: &str) -> bool {
+    let config = match load_config(path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    let problems = validate_config(&config, &HTTP_PORTS, &HTTPS_PORTS);
+    for problem in &problems {
+        eprintln!("{}: {}", path, problem);
+    }
+    problems.is_empty()
 }
 
 #[actix_web::main]
@@ -373,8 +1006,49 @@ async fn main() -> std::io::Result<()> {
     // 获取命令行参数
     let result = Args::try_parse();
     match result {
+        Ok(Args {
+            command: Some(Commands::Check { config }),
+            ..
+        }) => {
+            if !check_config(&config) {
+                std::process::exit(1);
+            }
+            println!("{}: 检查通过", config);
+        }
         Ok(args) => {
-            let shared_state = web::Data::new(AppState { args: args.clone() });
+            // 启动前检查配置文件，避免请求时才发现问题
+            if !check_config(CONFIG_FILE_PATH) {
+                eprintln!("配置文件有问题，请修改后再启动（可以使用 check 子命令检查）");
+                std::process::exit(1);
+            }
+            if let Err(e) = Encoding::parse(&args.encode) {
+                eprintln!("--encode：{}", e);
+                std::process::exit(1);
+            }
+            let shared_state = web::Data::new(AppState::new(args.clone()));
+
+            // 定时检查配置文件和模板是否有修改
+            if args.reload_interval > 0 {
+                let state = shared_state.clone();
+                let interval = std::time::Duration::from_secs(args.reload_interval);
+                actix_web::rt::spawn(async move {
+                    loop {
+                        actix_web::rt::time::sleep(interval).await;
+                        state.reload_if_changed();
+                    }
+                });
+            }
+            // 定时保存每个token的请求次数，写文件不占用处理请求的线程
+            {
+                let state = shared_state.clone();
+                actix_web::rt::spawn(async move {
+                    loop {
+                        actix_web::rt::time::sleep(QUOTA_FLUSH_INTERVAL).await;
+                        let state = state.clone();
+                        let _ = tokio::task::spawn_blocking(move || state.quota.flush()).await;
+                    }
+                });
+            }
             // 获取本机的私有IP地址
             let local_ip = match local_ip() {
                 Ok(ip) => ip,
@@ -387,20 +1061,23 @@ async fn main() -> std::io::Result<()> {
             let port = args.port;
             println!(
                 "Server is running on http://{}:{} or http://127.0.0.1:{}",
-                local_ip.to_string(),
-                port,
-                port
+                local_ip, port, port
             );
             // 创建并运行HTTP服务器
-            return HttpServer::new(move || {
+            let app_state = shared_state.clone();
+            let result = HttpServer::new(move || {
                 App::new()
-                    .app_data(shared_state.clone())
+                    .app_data(app_state.clone())
                     .service(index)
                     .service(subconverter)
+                    .service(subconverter_with_token)
             })
             .bind(format!("0.0.0.0:{}", port))?
             .run()
             .await;
+            // 退出前保存还没有保存的请求次数
+            shared_state.quota.flush();
+            return result;
         }
         Err(e) => {
             if e.kind() == ErrorKind::MissingRequiredArgument || e.kind() == ErrorKind::InvalidValue
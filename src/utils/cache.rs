@@ -0,0 +1,118 @@
+use crate::utils::hash::stable_hash;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// 内存中最多缓存多少个URL，超过时删除最久没有确认的
+const MAX_MEMORY_ENTRIES: usize = 64;
+
+/// 网络数据源的缓存选项
+#[derive(Debug, Clone)]
+pub struct CacheOptions {
+    pub ttl: Duration,        // 缓存的有效期，过期后向服务器确认是否有更新
+    pub dir: Option<PathBuf>, // 有值时，缓存同时保存到这个文件夹中，重启后还能使用
+}
+
+/// 缓存的响应内容，以及用于确认是否有更新的ETag、Last-Modified
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub url: String,
+    pub body: Arc<str>, // 内容可能很大，读取缓存时不复制内容
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub fetched_at: u64, // 最后一次确认内容的时间(Unix时间戳，秒)
+}
+
+impl CachedResponse {
+    pub fn new(
+        url: &str,
+        body: String,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    ) -> Self {
+        CachedResponse {
+            url: url.to_string(),
+            body: Arc::from(body),
+            etag,
+            last_modified,
+            fetched_at: now(),
+        }
+    }
+
+    /// 是否还在有效期内，在有效期内就不用请求服务器
+    pub fn is_fresh(&self, ttl: Duration) -> bool {
+        now().saturating_sub(self.fetched_at) < ttl.as_secs()
+    }
+
+    /// 服务器返回304(内容没有更新)时，刷新确认的时间
+    pub fn revalidated(mut self) -> Self {
+        self.fetched_at = now();
+        self
+    }
+}
+
+lazy_static! {
+    // 以URL为键的内存缓存
+    static ref MEMORY_CACHE: RwLock<HashMap<String, CachedResponse>> = RwLock::new(HashMap::new());
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// 缓存文件的路径，文件名使用URL的哈希值(算法固定，升级Rust后还能找到之前的缓存文件)
+fn cache_file(dir: &Path, url: &str) -> PathBuf {
+    dir.join(format!("{:016x}.json", stable_hash(&[url])))
+}
+
+/// 获取URL的缓存，先找内存，再找硬盘
+pub fn get(url: &str, options: &CacheOptions) -> Option<CachedResponse> {
+    if let Some(cached) = MEMORY_CACHE.read().unwrap().get(url) {
+        return Some(cached.clone());
+    }
+    let dir = options.dir.as_ref()?;
+    let content = std::fs::read_to_string(cache_file(dir, url)).ok()?;
+    let cached: CachedResponse = serde_json::from_str(&content).ok()?;
+    // 哈希值相同但URL不同的情况，当作没有缓存
+    if cached.url != url {
+        return None;
+    }
+    insert_memory(cached.clone());
+    Some(cached)
+}
+
+/// 保存URL的缓存，写入硬盘失败只打印错误，不影响内存缓存
+pub fn put(cached: CachedResponse, options: &CacheOptions) {
+    if let Some(dir) = options.dir.as_ref() {
+        let result = std::fs::create_dir_all(dir).and_then(|_| {
+            let content = serde_json::to_string(&cached).unwrap_or_default();
+            std::fs::write(cache_file(dir, &cached.url), content)
+        });
+        if let Err(e) = result {
+            eprintln!("保存 `{}` 的缓存失败：{}", cached.url, e);
+        }
+    }
+    insert_memory(cached);
+}
+
+// 保存到内存缓存，数量超过上限时，删除最久没有确认的URL
+fn insert_memory(cached: CachedResponse) {
+    let mut memory = MEMORY_CACHE.write().unwrap();
+    if !memory.contains_key(&cached.url) && memory.len() >= MAX_MEMORY_ENTRIES {
+        let oldest = memory
+            .values()
+            .min_by_key(|cached| cached.fetched_at)
+            .map(|cached| cached.url.clone());
+        if let Some(url) = oldest {
+            memory.remove(&url);
+        }
+    }
+    memory.insert(cached.url.clone(), cached);
+}
@@ -0,0 +1,209 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::{
+    collections::HashSet,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+/// 展开CIDR网段的方式
+#[derive(Debug, Clone, PartialEq)]
+pub enum CidrSampling {
+    Random(usize),      // 每个网段随机取N个IP
+    All { cap: usize }, // 按顺序取网段内所有的IP，每个网段最多取cap个
+}
+
+/// CIDR数据源的选项，seed有值时，每次展开的结果都一样
+#[derive(Debug, Clone, PartialEq)]
+pub struct CidrOptions {
+    pub sampling: CidrSampling,
+    pub seed: Option<u64>,
+}
+
+impl CidrOptions {
+    /// 解析URI参数的值：数字N表示每个网段随机取N个IP，all表示取所有IP（最多cap个）
+    pub fn parse(value: &str, seed: Option<u64>, cap: usize) -> Result<Self, String> {
+        let sampling = match value.trim().to_lowercase().as_str() {
+            "all" => CidrSampling::All { cap },
+            n => match n.parse::<usize>() {
+                Ok(n) if n > 0 => CidrSampling::Random(n.min(cap)),
+                _ => return Err(format!("cidr的值只能是all或大于0的数字，不能是：{}", value)),
+            },
+        };
+        Ok(CidrOptions { sampling, seed })
+    }
+
+    /// 创建展开网段的工具，没有seed时使用系统的随机源
+    pub fn expander(&self) -> CidrExpander {
+        let rng = match self.seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+        CidrExpander {
+            sampling: self.sampling.clone(),
+            rng,
+        }
+    }
+}
+
+/// 展开网段时，多个网段(多个文件)共用同一个随机数生成器，保证同一个seed的结果一样
+pub struct CidrExpander {
+    sampling: CidrSampling,
+    rng: ChaCha8Rng,
+}
+
+impl CidrExpander {
+    /// 展开一个网段，最多返回max个IP，已经够用时不会再展开剩下的IP
+    pub fn expand(&mut self, cidr: &str, max: usize) -> Result<Vec<String>, String> {
+        expand_cidr(cidr, &self.sampling, max, &mut self.rng)
+    }
+}
+
+// 将"IP/前缀长度"形式的网段展开为IP地址(最多max个)，IPv6地址会加上方括号
+fn expand_cidr(
+    cidr: &str,
+    sampling: &CidrSampling,
+    max: usize,
+    rng: &mut ChaCha8Rng,
+) -> Result<Vec<String>, String> {
+    let (ip, prefix) = cidr
+        .trim()
+        .split_once('/')
+        .ok_or(format!("`{}` 不是CIDR格式", cidr))?;
+    let ip: IpAddr = ip
+        .trim()
+        .trim_matches(|c| c == '[' || c == ']')
+        .parse()
+        .map_err(|_| format!("`{}` 中的IP地址无效", cidr))?;
+    let prefix: u32 = prefix
+        .trim()
+        .parse()
+        .map_err(|_| format!("`{}` 中的前缀长度无效", cidr))?;
+
+    // 统一转换为u128计算，IPv4的地址位数是32，IPv6是128
+    let (base, bits) = match ip {
+        IpAddr::V4(v4) => (u32::from(v4) as u128, 32),
+        IpAddr::V6(v6) => (u128::from(v6), 128),
+    };
+    if prefix > bits {
+        return Err(format!("`{}` 的前缀长度超出范围", cidr));
+    }
+    let host_bits = bits - prefix;
+    let network = match host_bits {
+        128 => 0,
+        _ => base >> host_bits << host_bits,
+    };
+    // 网段内IP的数量，/0的IPv6网段超出u128的范围，当作u128::MAX处理
+    let size = match host_bits {
+        128 => u128::MAX,
+        _ => 1u128 << host_bits,
+    };
+
+    let offsets: Vec<u128> = match *sampling {
+        CidrSampling::All { cap } => (0..size.min(cap.min(max) as u128)).collect(),
+        CidrSampling::Random(n) if n.min(max) as u128 >= size => (0..size).collect(),
+        CidrSampling::Random(n) => {
+            let n = n.min(max);
+            // 不重复地随机取n个偏移量，保持抽取的顺序
+            let mut seen = HashSet::new();
+            let mut offsets = Vec::with_capacity(n);
+            while offsets.len() < n {
+                let offset = rng.gen_range(0..size);
+                if seen.insert(offset) {
+                    offsets.push(offset);
+                }
+            }
+            offsets
+        }
+    };
+
+    let addrs = offsets
+        .into_iter()
+        .map(|offset| match ip {
+            IpAddr::V4(_) => Ipv4Addr::from((network + offset) as u32).to_string(),
+            IpAddr::V6(_) => format!("[{}]", Ipv6Addr::from(network + offset)),
+        })
+        .collect();
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expander(value: &str, seed: u64) -> CidrExpander {
+        CidrOptions::parse(value, Some(seed), 10000)
+            .unwrap()
+            .expander()
+    }
+
+    #[test]
+    fn parses_sampling() {
+        assert_eq!(
+            CidrOptions::parse("ALL", None, 100).unwrap().sampling,
+            CidrSampling::All { cap: 100 }
+        );
+        assert_eq!(
+            CidrOptions::parse("5", None, 100).unwrap().sampling,
+            CidrSampling::Random(5)
+        );
+        // 超过cap时按cap计算
+        assert_eq!(
+            CidrOptions::parse("500", None, 100).unwrap().sampling,
+            CidrSampling::Random(100)
+        );
+        assert!(CidrOptions::parse("0", None, 100).is_err());
+        assert!(CidrOptions::parse("abc", None, 100).is_err());
+    }
+
+    #[test]
+    fn all_expands_in_order_up_to_cap() {
+        let mut expander = expander("all", 0);
+        assert_eq!(
+            expander.expand("104.16.0.5/30", 100).unwrap(),
+            ["104.16.0.4", "104.16.0.5", "104.16.0.6", "104.16.0.7"]
+        );
+        // 超过max时只取前max个
+        assert_eq!(expander.expand("104.16.0.0/16", 3).unwrap().len(), 3);
+
+        let mut capped = CidrOptions::parse("all", None, 2).unwrap().expander();
+        assert_eq!(
+            capped.expand("104.16.0.0/24", 100).unwrap(),
+            ["104.16.0.0", "104.16.0.1"]
+        );
+    }
+
+    #[test]
+    fn random_samples_distinct_addresses_in_range() {
+        let mut expander = expander("50", 7);
+        let addrs = expander.expand("10.0.0.0/24", 100).unwrap();
+        assert_eq!(addrs.len(), 50);
+        assert_eq!(addrs.iter().collect::<HashSet<_>>().len(), 50);
+        assert!(addrs.iter().all(|addr| addr.starts_with("10.0.0.")));
+        // 网段比N小时，取网段内所有的IP
+        assert_eq!(expander.expand("10.0.0.0/30", 100).unwrap().len(), 4);
+        // 不超过max
+        assert_eq!(expander.expand("10.0.0.0/24", 10).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn random_is_deterministic_for_seed() {
+        let first = expander("5", 42).expand("2606:4700::/32", 100).unwrap();
+        assert_eq!(
+            first,
+            expander("5", 42).expand("2606:4700::/32", 100).unwrap()
+        );
+        assert_ne!(
+            first,
+            expander("5", 43).expand("2606:4700::/32", 100).unwrap()
+        );
+        assert!(first.iter().all(|addr| addr.starts_with("[2606:4700:")));
+    }
+
+    #[test]
+    fn rejects_invalid_cidr() {
+        let mut expander = expander("all", 0);
+        assert!(expander.expand("104.16.0.0", 10).is_err());
+        assert!(expander.expand("104.16.0.0/33", 10).is_err());
+        assert!(expander.expand("abc/24", 10).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+/// 处理ini风格的配置模板(surge、quanx)：将节点插入proxy_section中，并将group_section中的{all}替换为节点名称
+pub fn add_conf_template(
+    template: &str,
+    proxy_section: &str,
+    group_section: &str,
+    proxies_vec: Vec<(String, String)>,
+) -> String {
+    let proxy_lines: Vec<&str> = proxies_vec
+        .iter()
+        .filter(|(k, _)| !k.is_empty())
+        .map(|(_, v)| v.as_str())
+        .collect();
+    let proxy_names: String = proxies_vec
+        .iter()
+        .filter(|(k, _)| !k.is_empty())
+        .map(|(k, _)| k.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut output: Vec<String> = Vec::new();
+    let mut section = String::new();
+    let mut inserted = false;
+    for line in template.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            section = trimmed.to_lowercase();
+            output.push(line.to_string());
+            if section == proxy_section.to_lowercase() {
+                output.extend(proxy_lines.iter().map(|s| s.to_string()));
+                inserted = true;
+            }
+            continue;
+        }
+        if section == group_section.to_lowercase() && line.contains("{all}") {
+            // 没有节点时，去掉占位符及其前面的逗号
+            let replaced = match proxy_names.is_empty() {
+                true => line.replace(", {all}", "").replace("{all}", ""),
+                false => line.replace("{all}", &proxy_names),
+            };
+            output.push(replaced);
+        } else {
+            output.push(line.to_string());
+        }
+    }
+    // 模板中没有proxy_section时，追加到末尾
+    if !inserted {
+        output.push(proxy_section.to_string());
+        output.extend(proxy_lines.iter().map(|s| s.to_string()));
+    }
+
+    output.join("\n")
+}
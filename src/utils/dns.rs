@@ -0,0 +1,354 @@
+use crate::utils::{file_data::MyData, toml::Dns};
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    system_conf::read_system_conf,
+    TokioAsyncResolver,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::task::JoinSet;
+
+// 同时查询的域名数量，避免数据源中有大量域名时，一次发出太多DNS请求
+const MAX_CONCURRENT_LOOKUPS: usize = 16;
+
+/// 域名数据的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ResolveMode {
+    pub replace: bool, // 将域名替换为解析到的所有IP
+    pub drop: bool,    // 剔除解析不到IP的域名
+}
+
+impl ResolveMode {
+    /// 解析URI参数的值，如："keep"、"replace"、"drop"、"replace,drop"
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut mode = ResolveMode::default();
+        for flag in value.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            match flag.to_lowercase().as_str() {
+                "keep" => {}
+                "replace" => mode.replace = true,
+                "drop" => mode.drop = true,
+                _ => {
+                    return Err(format!(
+                        "resolve的值只能是keep、replace、drop，不能是：{}",
+                        flag
+                    ))
+                }
+            }
+        }
+        Ok(mode)
+    }
+
+    // keep只在别名中加上域名，不需要查询DNS
+    fn needs_lookup(&self) -> bool {
+        self.replace || self.drop
+    }
+}
+
+/// 域名解析器，先查config.toml中的[dns.hosts]，再查DNS服务器；server = "stub"时只使用[dns.hosts]
+#[derive(Clone)]
+pub struct DomainResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+    resolver: Option<TokioAsyncResolver>,
+}
+
+impl DomainResolver {
+    pub fn new(config: &Dns) -> Result<Self, String> {
+        let hosts = parse_hosts(&config.hosts)?;
+        let resolver = match config.server.trim().to_lowercase().as_str() {
+            "stub" => None,
+            "system" | "" => {
+                // 沿用系统的DNS服务器和其它选项，超时和重试次数仍以[dns]为准
+                let (resolver_config, opts) =
+                    read_system_conf().map_err(|e| format!("读取系统的DNS配置失败：{}", e))?;
+                Some(TokioAsyncResolver::tokio(
+                    resolver_config,
+                    apply_timeout(opts, config.timeout),
+                ))
+            }
+            _ => {
+                let server = parse_server(&config.server)?;
+                let group =
+                    NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port(), true);
+                let resolver_config = ResolverConfig::from_parts(None, vec![], group);
+                Some(TokioAsyncResolver::tokio(
+                    resolver_config,
+                    apply_timeout(ResolverOpts::default(), config.timeout),
+                ))
+            }
+        };
+        Ok(DomainResolver { hosts, resolver })
+    }
+
+    /// 查询域名的所有IP，查询失败时返回空的列表
+    pub async fn lookup(&self, domain: &str) -> Vec<IpAddr> {
+        let domain = domain.trim().trim_end_matches('.').to_lowercase();
+        if let Some(ips) = self.hosts.get(&domain) {
+            return ips.clone();
+        }
+        match &self.resolver {
+            Some(resolver) => match resolver.lookup_ip(format!("{}.", domain)).await {
+                Ok(lookup) => {
+                    // DNS服务器返回的顺序可能不一样，排序后保证每次生成的订阅一样
+                    let mut ips: Vec<IpAddr> = lookup.iter().collect();
+                    ips.sort();
+                    ips
+                }
+                Err(e) => {
+                    eprintln!("解析域名 `{}` 失败：{}", domain, e);
+                    Vec::new()
+                }
+            },
+            None => Vec::new(),
+        }
+    }
+}
+
+// 每个DNS查询只等待[dns].timeout秒，不重试，避免一个域名拖慢整个订阅
+fn apply_timeout(mut opts: ResolverOpts, timeout: u64) -> ResolverOpts {
+    opts.timeout = Duration::from_secs(timeout.max(1));
+    opts.attempts = 1;
+    opts
+}
+
+/// 所有请求共用的解析器(共用DNS缓存和连接)，只有config.toml中的[dns]修改后才重新创建
+#[derive(Default)]
+pub struct SharedResolver {
+    current: Mutex<Option<(Dns, DomainResolver)>>,
+}
+
+impl SharedResolver {
+    pub fn get(&self, config: &Dns) -> Result<DomainResolver, String> {
+        let mut current = self.current.lock().unwrap();
+        match current.as_ref() {
+            Some((dns, resolver)) if dns == config => Ok(resolver.clone()),
+            _ => {
+                let resolver = DomainResolver::new(config)?;
+                *current = Some((config.clone(), resolver.clone()));
+                Ok(resolver)
+            }
+        }
+    }
+}
+
+/// 检查DNS服务器的地址，如："1.1.1.1"、"8.8.8.8:53"、"[2606:4700:4700::1111]:53"
+pub fn parse_server(server: &str) -> Result<SocketAddr, String> {
+    let server = server.trim();
+    server
+        .parse::<SocketAddr>()
+        .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|_| format!("`{}` 不是有效的DNS服务器地址", server))
+}
+
+/// 检查[dns.hosts]中的IP地址，域名统一转换为小写
+pub fn parse_hosts(
+    hosts: &HashMap<String, Vec<String>>,
+) -> Result<HashMap<String, Vec<IpAddr>>, String> {
+    hosts
+        .iter()
+        .map(|(domain, ips)| {
+            let ips = ips
+                .iter()
+                .map(|ip| {
+                    ip.trim()
+                        .trim_matches(|c| c == '[' || c == ']')
+                        .parse::<IpAddr>()
+                        .map_err(|_| format!("`{}` 中的 `{}` 不是有效的IP地址", domain, ip))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((domain.trim().trim_end_matches('.').to_lowercase(), ips))
+        })
+        .collect()
+}
+
+// 地址是否为IP(IPv6地址可能带有方括号)
+fn is_ip(addr: &str) -> bool {
+    addr.trim_matches(|c| c == '[' || c == ']')
+        .parse::<IpAddr>()
+        .is_ok()
+}
+
+// 在别名后面加上域名，如："HKG" => "HKG example.com"
+fn tag_alias(alias: &Option<String>, domain: &str) -> Option<String> {
+    match alias.as_deref().map(str::trim) {
+        Some(alias) if !alias.is_empty() => Some(format!("{} {}", alias, domain)),
+        _ => Some(domain.to_string()),
+    }
+}
+
+/// 按指定的方式处理数据中的域名，替换后相同的地址和端口只保留第一个
+pub async fn resolve_domains(
+    datas: Vec<MyData>,
+    mode: ResolveMode,
+    resolver: &DomainResolver,
+) -> Vec<MyData> {
+    // 查询所有不重复的域名，最多同时查询MAX_CONCURRENT_LOOKUPS个
+    let mut resolved: HashMap<String, Vec<IpAddr>> = HashMap::new();
+    if mode.needs_lookup() {
+        let domains: HashSet<String> = datas
+            .iter()
+            .filter(|item| !is_ip(&item.addr))
+            .map(|item| item.addr.clone())
+            .collect();
+        let mut tasks = JoinSet::new();
+        for domain in domains {
+            if tasks.len() >= MAX_CONCURRENT_LOOKUPS {
+                if let Some(Ok((domain, ips))) = tasks.join_next().await {
+                    resolved.insert(domain, ips);
+                }
+            }
+            let resolver = resolver.clone();
+            tasks.spawn(async move {
+                let ips = resolver.lookup(&domain).await;
+                (domain, ips)
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            if let Ok((domain, ips)) = result {
+                resolved.insert(domain, ips);
+            }
+        }
+    }
+
+    let mut seen_addr: HashSet<String> = HashSet::new();
+    let mut results: Vec<MyData> = Vec::new();
+    for item in datas {
+        if is_ip(&item.addr) {
+            if seen_addr.insert(format!("{}:{}", item.addr, item.port.unwrap_or(0))) {
+                results.push(item);
+            }
+            continue;
+        }
+        let ips = resolved.get(&item.addr).cloned().unwrap_or_default();
+        if ips.is_empty() && mode.drop {
+            continue;
+        }
+        let alias = tag_alias(&item.alias, &item.addr);
+        let addrs: Vec<String> = match mode.replace && !ips.is_empty() {
+            true => ips
+                .iter()
+                .map(|ip| match ip {
+                    IpAddr::V4(v4) => v4.to_string(),
+                    IpAddr::V6(v6) => format!("[{}]", v6),
+                })
+                .collect(),
+            false => vec![item.addr.clone()],
+        };
+        for addr in addrs {
+            if seen_addr.insert(format!("{}:{}", addr, item.port.unwrap_or(0))) {
+                results.push(MyData {
+                    addr,
+                    alias: alias.clone(),
+                    ..item.clone()
+                });
+            }
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stub_resolver() -> DomainResolver {
+        let config = Dns {
+            server: "stub".to_string(),
+            hosts: HashMap::from([(
+                "a.example.com".to_string(),
+                vec!["1.1.1.2".to_string(), "1.1.1.1".to_string()],
+            )]),
+            ..Default::default()
+        };
+        DomainResolver::new(&config).unwrap()
+    }
+
+    fn data(addr: &str, alias: &str) -> MyData {
+        MyData {
+            addr: addr.to_string(),
+            port: Some(443),
+            alias: Some(alias.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn sample() -> Vec<MyData> {
+        vec![
+            data("A.example.com", "HKG"),
+            data("missing.example.com", ""),
+            data("1.1.1.1", "SJC"),
+        ]
+    }
+
+    fn summary(datas: &[MyData]) -> Vec<(String, String)> {
+        datas
+            .iter()
+            .map(|item| (item.addr.clone(), item.alias.clone().unwrap_or_default()))
+            .collect()
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|(addr, alias)| (addr.to_string(), alias.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(ResolveMode::parse("keep").unwrap(), ResolveMode::default());
+        let mode = ResolveMode::parse("replace, drop").unwrap();
+        assert!(mode.replace && mode.drop);
+        assert!(ResolveMode::parse("both").is_err());
+    }
+
+    #[tokio::test]
+    async fn stub_only_uses_hosts() {
+        let resolver = stub_resolver();
+        let ips = resolver.lookup("A.Example.com.").await;
+        assert_eq!(ips.len(), 2);
+        assert!(resolver.lookup("missing.example.com").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn keep_only_tags_alias() {
+        let mode = ResolveMode::parse("keep").unwrap();
+        let results = resolve_domains(sample(), mode, &stub_resolver()).await;
+        assert_eq!(
+            summary(&results),
+            pairs(&[
+                ("A.example.com", "HKG A.example.com"),
+                ("missing.example.com", "missing.example.com"),
+                ("1.1.1.1", "SJC"),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn replace_expands_domain_and_dedupes() {
+        let mode = ResolveMode::parse("replace").unwrap();
+        let results = resolve_domains(sample(), mode, &stub_resolver()).await;
+        // 1.1.1.1:443已经由域名替换得到，后面的IP数据被去重；解析不到的域名保留
+        assert_eq!(
+            summary(&results),
+            pairs(&[
+                ("1.1.1.2", "HKG A.example.com"),
+                ("1.1.1.1", "HKG A.example.com"),
+                ("missing.example.com", "missing.example.com"),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn drop_removes_unresolved_domains() {
+        let mode = ResolveMode::parse("drop").unwrap();
+        let results = resolve_domains(sample(), mode, &stub_resolver()).await;
+        assert_eq!(
+            summary(&results),
+            pairs(&[("A.example.com", "HKG A.example.com"), ("1.1.1.1", "SJC")])
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use base64::{
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD},
+    Engine,
+};
+
+/// 订阅内容的编码方式，可选：plain、base64、base64-nopad、base64url、base64url-nopad
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Plain,
+    Base64,
+    Base64NoPad,
+    Base64Url,
+    Base64UrlNoPad,
+}
+
+impl Encoding {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().replace('_', "-").as_str() {
+            "plain" => Ok(Encoding::Plain),
+            "base64" => Ok(Encoding::Base64),
+            "base64-nopad" => Ok(Encoding::Base64NoPad),
+            "base64url" | "urlsafe" => Ok(Encoding::Base64Url),
+            "base64url-nopad" | "urlsafe-nopad" => Ok(Encoding::Base64UrlNoPad),
+            _ => Err(format!(
+                "encode的值只能是plain、base64、base64-nopad、base64url、base64url-nopad，不能是：{}",
+                value
+            )),
+        }
+    }
+
+    pub fn encode(&self, content: &str) -> String {
+        match self {
+            Encoding::Plain => content.to_string(),
+            Encoding::Base64 => STANDARD.encode(content),
+            Encoding::Base64NoPad => STANDARD_NO_PAD.encode(content),
+            Encoding::Base64Url => URL_SAFE.encode(content),
+            Encoding::Base64UrlNoPad => URL_SAFE_NO_PAD.encode(content),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 标准和URL安全的base64编码后的字符不同（>>?的base64是Pj4/）
+    const CONTENT: &str = ">>?";
+
+    #[test]
+    fn parses_encoding_names() {
+        assert_eq!(Encoding::parse(" Plain "), Ok(Encoding::Plain));
+        assert_eq!(Encoding::parse("base64_nopad"), Ok(Encoding::Base64NoPad));
+        assert_eq!(Encoding::parse("urlsafe"), Ok(Encoding::Base64Url));
+        assert_eq!(
+            Encoding::parse("BASE64URL-NOPAD"),
+            Ok(Encoding::Base64UrlNoPad)
+        );
+        assert!(Encoding::parse("hex").is_err());
+    }
+
+    #[test]
+    fn encodes_each_variant() {
+        assert_eq!(Encoding::Plain.encode(CONTENT), ">>?");
+        assert_eq!(Encoding::Base64.encode(CONTENT), "Pj4/");
+        assert_eq!(Encoding::Base64Url.encode(CONTENT), "Pj4_");
+        assert_eq!(Encoding::Base64.encode("a"), "YQ==");
+        assert_eq!(Encoding::Base64NoPad.encode("a"), "YQ");
+        assert_eq!(Encoding::Base64Url.encode("a?"), "YT8=");
+        assert_eq!(Encoding::Base64UrlNoPad.encode("a?"), "YT8");
+        assert_eq!(Encoding::Base64UrlNoPad.encode(CONTENT), "Pj4_");
+    }
+}
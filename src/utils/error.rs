@@ -0,0 +1,100 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde_json::json;
+use std::fmt;
+
+/// 订阅转换过程中的错误，每种错误对应一个HTTP状态码，方便监控区分"没有数据"和"出错了"
+#[derive(Debug)]
+pub enum SubError {
+    BadRequest(String),                                    // 400：URI参数有问题
+    Forbidden(String),                                     // 403：没有权限访问
+    PageNotFound { page: usize, pages: usize },            // 404：页码超出范围
+    TooManyRequests { message: String, retry_after: u64 }, // 429：超出请求次数，retry_after秒后再试
+    Config(String),                                        // 500：config.toml没有加载成功
+    Template(String),                                      // 500：配置模板没有加载成功
+    Upstream(String),                                      // 502：获取网络数据源失败
+}
+
+impl SubError {
+    // 错误类型的简短名称，放在JSON的error字段中
+    fn kind(&self) -> &'static str {
+        match self {
+            SubError::BadRequest(_) => "bad_request",
+            SubError::Forbidden(_) => "forbidden",
+            SubError::PageNotFound { .. } => "page_not_found",
+            SubError::TooManyRequests { .. } => "too_many_requests",
+            SubError::Config(_) => "config_error",
+            SubError::Template(_) => "template_error",
+            SubError::Upstream(_) => "upstream_error",
+        }
+    }
+}
+
+impl fmt::Display for SubError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubError::BadRequest(msg) => write!(f, "参数错误：{}", msg),
+            SubError::Forbidden(msg) => write!(f, "没有权限：{}", msg),
+            SubError::PageNotFound { page, pages } => {
+                write!(f, "无效的页码：{}，总共只有{}页", page, pages)
+            }
+            SubError::TooManyRequests { message, .. } => write!(f, "请求太多：{}", message),
+            SubError::Config(msg) => write!(f, "配置文件错误：{}", msg),
+            SubError::Template(msg) => write!(f, "模板文件错误：{}", msg),
+            SubError::Upstream(msg) => write!(f, "获取数据源失败：{}", msg),
+        }
+    }
+}
+
+impl ResponseError for SubError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            SubError::Forbidden(_) => StatusCode::FORBIDDEN,
+            SubError::PageNotFound { .. } => StatusCode::NOT_FOUND,
+            SubError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            SubError::Config(_) | SubError::Template(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            SubError::Upstream(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    // 返回JSON格式的错误信息，如：{"error":"page_not_found","message":"无效的页码：3，总共只有1页"}
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let SubError::TooManyRequests { retry_after, .. } = self {
+            response.insert_header(("Retry-After", retry_after.to_string()));
+        }
+        response.json(json!({
+            "error": self.kind(),
+            "message": self.to_string(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn too_many_requests_sets_retry_after() {
+        let error = SubError::TooManyRequests {
+            message: "请求太频繁，请稍后再试".to_string(),
+            retry_after: 7,
+        };
+        let response = error.error_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            response
+                .headers()
+                .get("Retry-After")
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "7"
+        );
+        assert!(SubError::BadRequest("x".to_string())
+            .error_response()
+            .headers()
+            .get("Retry-After")
+            .is_none());
+    }
+}
@@ -1,13 +1,15 @@
+use crate::utils::{ cidr::{ CidrExpander, CidrOptions }, locations::fill_location };
 use csv::ReaderBuilder;
 use std::{
     collections::HashMap,
     error::Error,
     fs::{ self, File },
-    io::{ BufRead, BufReader },
+    io::Read,
     path::Path,
     vec,
 };
 use regex::Regex;
+use serde_json::Value as JsonValue;
 use lazy_static::lazy_static;
 
 #[allow(dead_code)]
@@ -16,6 +18,13 @@ pub struct MyData {
     pub addr: String,
     pub port: Option<u16>,
     pub alias: Option<String>,
+    pub colo: Option<String>, // 数据中心(3位字母)
+    pub loc: Option<String>, // 国家代码/地区代码(2位字母)
+    pub region: Option<String>, // 地区
+    pub city: Option<String>,
+    pub latency: Option<f64>, // 平均延迟(ms)
+    pub loss: Option<f64>, // 丢包率(0~1)
+    pub speed: Option<f64>, // 下载速度(MB/s)
 }
 
 #[derive(Default)]
@@ -26,6 +35,9 @@ pub struct FileData {
     pub loc: Option<String>, // 国家代码/地区代码(2位字母)
     pub region: Option<String>, // 地区
     pub city: Option<String>,
+    pub latency: Option<f64>, // 平均延迟(ms)
+    pub loss: Option<f64>, // 丢包率(0~1)
+    pub speed: Option<f64>, // 下载速度(MB/s)
 }
 
 lazy_static! {
@@ -52,18 +64,158 @@ lazy_static! {
 pub fn create_field_map() -> HashMap<&'static str, Vec<&'static str>> {
     // 可能的字段映射关系，以列名作为键，其它别名的列名作为值(向量)
     let mut field_map: HashMap<&str, Vec<&str>> = HashMap::new();
-    field_map.insert("addr", vec!["IP", "IP地址", "IP 地址", "网络地址"]);
+    field_map.insert("addr", vec!["IP", "IP地址", "IP 地址", "网络地址", "addr", "address"]);
     field_map.insert("port", vec!["PORT", "端口"]);
     field_map.insert("colo", vec!["colo", "iata", "数据中心"]);
     field_map.insert("loc", vec!["cca2", "alpha-2", "Country Code", "CountryCode", "国家代码"]);
     field_map.insert("region", vec!["region", "区域", "地区"]);
     field_map.insert("city", vec!["city", "城市"]);
+    field_map.insert("latency", vec!["平均延迟", "网络延迟", "延迟", "latency", "avg latency", "delay"]);
+    field_map.insert("loss", vec!["丢包率", "loss", "loss rate", "packet loss"]);
+    field_map.insert(
+        "speed",
+        vec!["下载速度 (MB/s)", "下载速度(MB/s)", "下载速度", "speed", "download speed", "download speed (MB/s)"]
+    );
     field_map
 }
 
+/// 解析测速结果中的数值，如："57 ms"、"0.25"、"25%"、"12.5 MB/s"，百分数转换为小数
+pub fn parse_metric(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let number = value.trim_end_matches(|c: char| !c.is_ascii_digit()).trim();
+    let metric = number.parse::<f64>().ok()?;
+    match value.ends_with('%') {
+        true => Some(metric / 100.0),
+        false => Some(metric),
+    }
+}
+
+/// 根据文件名(或链接)的扩展名，判断数据的格式，不支持的格式返回None
+pub fn file_type(name: &str) -> Option<&'static str> {
+    // 链接中可能含有查询参数，如：https://x.com/result.json?token=xxx
+    let name = name.split(['?', '#']).next().unwrap_or_default().to_lowercase();
+    let extension = Path::new(&name).extension().and_then(|ext| ext.to_str())?;
+    match extension {
+        "csv" => Some("csv"),
+        "txt" => Some("txt"),
+        "json" => Some("json"),
+        "jsonl" | "ndjson" => Some("jsonl"),
+        "yaml" | "yml" => Some("yaml"),
+        _ => None,
+    }
+}
+
+/// 解析json、jsonl、yaml格式的数据，本地文件和网络文件共用
+pub fn parse_structured(
+    file_type: &str,
+    content: &str,
+    default_port: u16
+) -> Result<Vec<FileData>, Box<dyn Error>> {
+    let values: Vec<JsonValue> = match file_type {
+        "json" => vec![serde_json::from_str(content)?],
+        "yaml" => vec![serde_yaml::from_str(content)?],
+        "jsonl" =>
+            content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<Result<_, _>>()?,
+        _ => {
+            return Err(format!("不支持的文件类型：{}", file_type).into());
+        }
+    };
+
+    // 支持数组、{"data":[...]}这样包了一层的对象，数组元素可以是对象或者"IP:PORT"这样的字符串
+    let items: Vec<JsonValue> = values
+        .into_iter()
+        .flat_map(|value| match value {
+            JsonValue::Array(items) => items,
+            JsonValue::Object(map) if map.values().any(JsonValue::is_array) =>
+                map
+                    .into_iter()
+                    .find_map(|(_, value)| match value {
+                        JsonValue::Array(items) => Some(items),
+                        _ => None,
+                    })
+                    .unwrap_or_default(),
+            value => vec![value],
+        })
+        .collect();
+
+    let field_map = create_field_map();
+    let mut lines: Vec<String> = Vec::new();
+    let mut result: Vec<FileData> = Vec::new();
+
+    for item in &items {
+        let object = match item {
+            JsonValue::Object(object) => object,
+            JsonValue::String(line) => {
+                lines.push(line.to_string());
+                continue;
+            }
+            _ => {
+                continue;
+            }
+        };
+
+        // 跟csv文件的列名一样，对象的键名也使用create_field_map中的别名(忽略大小写)
+        let get_field = |key: &str| -> Option<String> {
+            let value = field_map.get(key).and_then(|candidates|
+                candidates.iter().find_map(|&field|
+                    object
+                        .iter()
+                        .find(|(k, _)| k.trim().to_lowercase() == field.trim().to_lowercase())
+                        .map(|(_, v)| v)
+                )
+            )?;
+            match value {
+                JsonValue::String(s) => Some(s.trim().to_string()),
+                JsonValue::Number(n) => Some(n.to_string()),
+                JsonValue::Bool(b) => Some(b.to_string()),
+                _ => None,
+            }
+        };
+
+        let addr = get_field("addr").unwrap_or_default();
+        if addr.is_empty() {
+            continue;
+        }
+        let mut data = FileData {
+            addr,
+            port: Some(
+                get_field("port")
+                    .and_then(|port| port.parse::<u16>().ok())
+                    .unwrap_or(default_port)
+            ),
+            colo: Some(get_field("colo").unwrap_or_default()),
+            loc: Some(get_field("loc").unwrap_or_default()),
+            region: Some(get_field("region").unwrap_or_default()),
+            city: Some(get_field("city").unwrap_or_default()),
+            latency: get_field("latency").as_deref().and_then(parse_metric),
+            loss: get_field("loss").as_deref().and_then(parse_metric),
+            speed: get_field("speed").as_deref().and_then(parse_metric),
+        };
+        // 只有数据中心的数据，根据数据中心补全国家代码、地区、城市
+        fill_location(&mut data);
+        result.push(data);
+    }
+
+    // 字符串形式的地址，跟txt文件一样解析
+    if !lines.is_empty() {
+        result.extend(parse_txt(&lines.join("\n"), default_port, None, usize::MAX));
+    }
+
+    Ok(result)
+}
+
 fn process_csv(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<dyn Error>> {
     let file = File::open(filename)?;
-    let mut rdr = ReaderBuilder::new().from_reader(file);
+    parse_csv(file, default_port)
+}
+
+/// 解析csv格式的数据，本地文件和网络文件共用
+pub fn parse_csv<R: Read>(reader: R, default_port: u16) -> Result<Vec<FileData>, Box<dyn Error>> {
+    let mut rdr = ReaderBuilder::new().from_reader(reader);
 
     // 读取文件头
     let headers = rdr.headers()?;
@@ -88,6 +240,9 @@ fn process_csv(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<d
     let loc_index = find_index("loc");
     let region_index = find_index("region");
     let city_index = find_index("city");
+    let latency_index = find_index("latency");
+    let loss_index = find_index("loss");
+    let speed_index = find_index("speed");
 
     let mut result: Vec<FileData> = Vec::new();
 
@@ -110,7 +265,7 @@ fn process_csv(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<d
         let get_column_string = |index: Option<usize>| {
             index
                 .and_then(|idx| record.get(idx).and_then(|val| val.parse().ok())) // 隐式转换
-                .unwrap_or_else(|| "".to_string()) // 默认为空字符串
+                .unwrap_or_default() // 默认为空字符串
         };
 
         // 使用闭包提取列数据，没有找到对应的列时，返回空字符串
@@ -119,35 +274,89 @@ fn process_csv(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<d
         let region_column = get_column_string(region_index);
         let city_column = get_column_string(city_index);
 
-        let data = FileData {
+        // 测速结果中的延迟、丢包率、下载速度，没有对应的列或不是数值时为None
+        let get_column_metric = |index: Option<usize>| {
+            index.and_then(|idx| record.get(idx)).and_then(parse_metric)
+        };
+
+        let mut data = FileData {
             addr: addr_column.to_string(),
             port: Some(port_column),
             colo: Some(colo_column),
             loc: Some(loc_column),
             region: Some(region_column),
             city: Some(city_column),
+            latency: get_column_metric(latency_index),
+            loss: get_column_metric(loss_index),
+            speed: get_column_metric(speed_index),
         };
+        // 只有数据中心的csv文件，根据数据中心补全国家代码、地区、城市
+        fill_location(&mut data);
         result.push(data);
     }
 
     Ok(result)
 }
 
-fn process_txt(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<dyn Error>> {
-    // 排除不需要的txt文件，注意：如果传入完整的路径，该功能不起作用
-    if filename.starts_with("ips-v") || filename.starts_with("ipv") {
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Skipping this file")));
+/// 展开"CIDR"、"CIDR PORT"、"CIDR,PORT"格式的一行数据(最多max个IP)，端口可以省略
+pub fn expand_cidr_line(line: &str, expander: &mut CidrExpander, max: usize) -> Vec<FileData> {
+    let mut parts = line.splitn(2, |c: char| c == ',' || c.is_whitespace());
+    let cidr = parts.next().unwrap_or_default();
+    let port = parts.next().and_then(|port| port.trim().parse::<u16>().ok());
+    match expander.expand(cidr, max) {
+        Ok(addrs) =>
+            addrs
+                .into_iter()
+                .map(|addr| FileData {
+                    addr,
+                    port,
+                    ..Default::default()
+                })
+                .collect(),
+        Err(e) => {
+            println!("不支持展开 `{}`：{}", line, e);
+            Vec::new()
+        }
     }
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
+}
+
+fn process_txt(
+    filename: &str,
+    default_port: u16,
+    cidr: Option<&mut CidrExpander>,
+    limit: usize
+) -> Result<Vec<FileData>, Box<dyn Error>> {
+    // 排除不需要的txt文件(没有开启CIDR展开时)，注意：如果传入完整的路径，该功能不起作用
+    if cidr.is_none() && (filename.starts_with("ips-v") || filename.starts_with("ipv")) {
+        return Err(Box::new(std::io::Error::other("Skipping this file")));
+    }
+    let content = fs::read_to_string(filename)?;
+    Ok(parse_txt(&content, default_port, cidr, limit))
+}
 
+/// 解析txt格式的数据(一行一个地址)，本地文件和网络文件共用，最多返回limit个地址
+pub fn parse_txt(
+    content: &str,
+    default_port: u16,
+    mut cidr: Option<&mut CidrExpander>,
+    limit: usize
+) -> Vec<FileData> {
     let mut seen_lines: Vec<String> = Vec::new();
     let mut result: Vec<FileData> = Vec::new();
 
-    for line in reader.lines() {
-        let line = line?;
+    for line in content.lines() {
+        // 已经够用了，不再解析剩下的行(避免展开大量的网段)
+        if result.len() >= limit {
+            break;
+        }
         let trimmed_line = line.trim().to_string();
 
+        // 开启CIDR展开时，将网段展开为IP地址，只展开还需要的数量
+        if let (true, Some(expander)) = (trimmed_line.contains('/'), cidr.as_deref_mut()) {
+            result.extend(expand_cidr_line(&trimmed_line, expander, limit - result.len()));
+            continue;
+        }
+
         let contains_bool = trimmed_line.contains("/") || seen_lines.contains(&trimmed_line);
         if trimmed_line.is_empty() || contains_bool {
             continue;
@@ -244,17 +453,20 @@ fn process_txt(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<d
         }
     }
 
-    Ok(result)
+    result
 }
 
-fn process_file(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<dyn Error>> {
-    let path = Path::new(filename);
-    let extension = path.extension().and_then(|s| s.to_str());
-
-    match extension {
+fn process_file(
+    filename: &str,
+    default_port: u16,
+    cidr: Option<&mut CidrExpander>,
+    limit: usize // txt文件最多读取多少个地址
+) -> Result<Vec<FileData>, Box<dyn Error>> {
+    match file_type(filename) {
         Some("csv") => process_csv(filename, default_port),
-        Some("txt") => process_txt(filename, default_port),
-        _ => Err("不支持的文件类型".into()),
+        Some("txt") => process_txt(filename, default_port, cidr, limit),
+        Some(file_type) => parse_structured(file_type, &fs::read_to_string(filename)?, default_port),
+        None => Err("不支持的文件类型".into()),
     }
 }
 
@@ -263,15 +475,17 @@ pub fn process_files_data(
     field_column: &str,
     default_port: u16,
     count: usize,
-    target_path: &str
-) -> Vec<MyData> {
+    target_path: &str,
+    cidr: Option<&CidrOptions> // 有值时，将txt文件中的CIDR网段展开为IP地址
+) -> Result<Vec<MyData>, String> {
     let mut results: Vec<MyData> = Vec::new(); // 存储结果
     let mut seen_addr: Vec<String> = Vec::new(); // 数据去重
+    let mut expander = cidr.map(CidrOptions::expander);
 
     let path = Path::new(target_path);
-    if path.is_file() && path.extension().map_or(false, |ext| (ext == "txt" || ext == "csv")) {
-        // 如果路径是 TXT 或 CSV 文件，直接处理该文件
-        match process_file(target_path, default_port) {
+    if path.is_file() && file_type(target_path).is_some() {
+        // 如果路径是 TXT、CSV、JSON、JSONL、YAML 文件，直接处理该文件
+        match process_file(target_path, default_port, expander.as_mut(), count) {
             Ok(data) => {
                 for item in &data {
                     let addr: String = item.addr.clone();
@@ -299,13 +513,20 @@ pub fn process_files_data(
                         addr: addr.clone(),
                         port: Some(port),
                         alias: alias_prefix,
+                        colo: item.colo.clone(),
+                        loc: item.loc.clone(),
+                        region: item.region.clone(),
+                        city: item.city.clone(),
+                        latency: item.latency,
+                        loss: item.loss,
+                        speed: item.speed,
                     };
 
                     // 如果结果数量小于指定的数量，则添加数据，否则就返回，避免无意义的IO操作(读取数据)
                     if results.len() < count {
                         results.push(data.clone());
                     } else {
-                        return results; // 直接返回结果，因为是处理单个文件
+                        return Ok(results); // 直接返回结果，因为是处理单个文件
                     }
                 }
             }
@@ -313,21 +534,26 @@ pub fn process_files_data(
         }
     } else if path.is_dir() {
         // 如果路径是文件夹，执行原来的逻辑
-        let msg = format!("文件夹 {} 不存在！", target_path);
-        let entries = fs::read_dir(target_path).expect(&msg);
-        'outer: for entry in entries {
-            let entry = entry.unwrap();
-            let path = entry.path();
-
-            let is_txt_or_csv = path
-                .extension()
-                .map_or(false, |ext| (ext == "txt" || ext == "csv"));
-
-            // 只处理txt和csv文件，process_file函数中，含有排除的txt文件
-            if path.is_file() && is_txt_or_csv {
+        let entries = fs::read_dir(target_path)
+            .map_err(|e| format!("读取文件夹 {} 失败：{}", target_path, e))?;
+        // 按文件名排序，保证每次读取文件的顺序一样
+        let mut paths: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+        paths.sort();
+        let real_dir = path.canonicalize().map_err(|e| format!("读取文件夹 {} 失败：{}", target_path, e))?;
+        'outer: for path in paths {
+            // 跳过指向文件夹外面的符号链接
+            if !path.canonicalize().is_ok_and(|real_path| real_path.starts_with(&real_dir)) {
+                continue;
+            }
+
+            let is_supported = file_type(&path.to_string_lossy()).is_some();
+
+            // 只处理支持的文件类型，process_file函数中，含有排除的txt文件
+            if path.is_file() && is_supported {
                 let file_name = path.file_name().unwrap().to_string_lossy();
                 let filename: String = format!("{}/{}", target_path, file_name);
-                match process_file(&filename, default_port) {
+                let remaining = count - results.len();
+                match process_file(&filename, default_port, expander.as_mut(), remaining) {
                     Ok(data) => {
                         for item in &data {
                             let addr: String = item.addr.clone();
@@ -355,6 +581,13 @@ pub fn process_files_data(
                                 addr: addr.clone(),
                                 port: Some(port),
                                 alias: alias_prefix,
+                                colo: item.colo.clone(),
+                                loc: item.loc.clone(),
+                                region: item.region.clone(),
+                                city: item.city.clone(),
+                                latency: item.latency,
+                                loss: item.loss,
+                                speed: item.speed,
                             };
 
                             // 获取足够的数据，就停止for循环
@@ -370,8 +603,69 @@ pub fn process_files_data(
             }
         }
     } else {
-        eprintln!("路径 {} 不是有效的文件或文件夹。", target_path);
+        return Err(format!("路径 {} 不是有效的文件或文件夹。", target_path));
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr_ports(datas: &[FileData]) -> Vec<String> {
+        datas
+            .iter()
+            .map(|item| format!("{}:{}", item.addr, item.port.unwrap_or(0)))
+            .collect()
     }
 
-    results
+    #[test]
+    fn detects_file_type_from_name() {
+        assert_eq!(file_type("./data/RESULT.JSON"), Some("json"));
+        assert_eq!(file_type("https://x.com/ips.ndjson?token=a.csv"), Some("jsonl"));
+        assert_eq!(file_type("ips.yml#top"), Some("yaml"));
+        assert_eq!(file_type("ips.xml"), None);
+    }
+
+    #[test]
+    fn parses_json_with_field_aliases() {
+        let content = r#"{"data": [
+                {"IP地址": "1.1.1.1", "端口": 8443, "数据中心": "HKG", "国家代码": "HK", "地区": "Asia Pacific", "城市": "Hong Kong", "平均延迟": "57 ms", "丢包率": "25%", "下载速度 (MB/s)": 12.5},
+                {"Address": "2.2.2.2", "LATENCY": 80},
+                {"port": 443},
+                "3.3.3.3:2053"
+            ]}"#;
+        let datas = parse_structured("json", content, 443).unwrap();
+        assert_eq!(addr_ports(&datas), ["1.1.1.1:8443", "2.2.2.2:443", "3.3.3.3:2053"]);
+        let first = &datas[0];
+        assert_eq!(first.colo.as_deref(), Some("HKG"));
+        assert_eq!(first.loc.as_deref(), Some("HK"));
+        assert_eq!(first.region.as_deref(), Some("Asia Pacific"));
+        assert_eq!(first.city.as_deref(), Some("Hong Kong"));
+        assert_eq!(first.latency, Some(57.0));
+        assert_eq!(first.loss, Some(0.25));
+        assert_eq!(first.speed, Some(12.5));
+        assert_eq!(datas[1].latency, Some(80.0));
+    }
+
+    #[test]
+    fn parses_jsonl_and_yaml() {
+        let jsonl = "{\"ip\": \"1.1.1.1\", \"delay\": 10}\n\n{\"addr\": \"2.2.2.2\", \"port\": \"2053\"}\n";
+        let datas = parse_structured("jsonl", jsonl, 443).unwrap();
+        assert_eq!(addr_ports(&datas), ["1.1.1.1:443", "2.2.2.2:2053"]);
+        assert_eq!(datas[0].latency, Some(10.0));
+
+        let yaml = "- ip: 1.1.1.1\n  port: 8443\n  loss rate: 0.1\n- 2.2.2.2\n";
+        let datas = parse_structured("yaml", yaml, 443).unwrap();
+        assert_eq!(addr_ports(&datas), ["1.1.1.1:8443", "2.2.2.2:443"]);
+        assert_eq!(datas[0].loss, Some(0.1));
+    }
+
+    #[test]
+    fn rejects_invalid_structured_content() {
+        assert!(parse_structured("json", "{", 443).is_err());
+        assert!(parse_structured("jsonl", "{\"ip\": \"1.1.1.1\"}\nnot json", 443).is_err());
+        assert!(parse_structured("csv", "ip\n1.1.1.1", 443).is_err());
+    }
 }
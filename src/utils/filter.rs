@@ -0,0 +1,224 @@
+use crate::utils::file_data::MyData;
+use regex::{Regex, RegexBuilder};
+use std::cmp::Ordering;
+
+/// 某个字段的包含/排除条件，每个条件都是忽略大小写、完整匹配的正则表达式
+#[derive(Debug, Default, Clone)]
+pub struct FieldFilter {
+    include: Vec<Regex>, // 匹配其中一个就保留
+    exclude: Vec<Regex>, // 匹配其中一个就剔除
+}
+
+impl FieldFilter {
+    /// 添加逗号分隔的条件，以!开头的是排除条件，如："HKG,NRT,SIN"、"!LAX"、"^S.*"
+    pub fn add(&mut self, value: &str) -> Result<(), String> {
+        for pattern in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (list, pattern) = match pattern.strip_prefix('!') {
+                Some(pattern) => (&mut self.exclude, pattern.trim()),
+                None => (&mut self.include, pattern),
+            };
+            let regex = RegexBuilder::new(&format!("^(?:{})$", pattern))
+                .case_insensitive(true)
+                .build()
+                .map_err(|_| format!("`{}` 不是有效的正则表达式", pattern))?;
+            list.push(regex);
+        }
+        Ok(())
+    }
+
+    fn matches(&self, value: Option<&str>) -> bool {
+        let value = value.unwrap_or_default().trim();
+        let included =
+            self.include.is_empty() || self.include.iter().any(|regex| regex.is_match(value));
+        included && !self.exclude.iter().any(|regex| regex.is_match(value))
+    }
+}
+
+/// 根据数据中心、国家代码、地区、城市过滤数据，多个字段的条件需要同时满足
+#[derive(Debug, Default, Clone)]
+pub struct LocationFilter {
+    pub colo: FieldFilter,
+    pub loc: FieldFilter,
+    pub region: FieldFilter,
+    pub city: FieldFilter,
+}
+
+impl LocationFilter {
+    pub fn matches(&self, item: &MyData) -> bool {
+        self.colo.matches(item.colo.as_deref())
+            && self.loc.matches(item.loc.as_deref())
+            && self.region.matches(item.region.as_deref())
+            && self.city.matches(item.city.as_deref())
+    }
+}
+
+/// 根据测速结果(延迟、丢包率、下载速度)过滤数据，设置了条件时，没有对应测速结果的数据也会被过滤掉
+#[derive(Debug, Default, Clone)]
+pub struct MetricFilter {
+    pub max_latency: Option<f64>, // 最大延迟(ms)
+    pub max_loss: Option<f64>,    // 最大丢包率(0~1)
+    pub min_speed: Option<f64>,   // 最小下载速度(MB/s)
+}
+
+impl MetricFilter {
+    pub fn matches(&self, item: &MyData) -> bool {
+        let at_most = |limit: Option<f64>, value: Option<f64>| match limit {
+            Some(limit) => value.is_some_and(|value| value <= limit),
+            None => true,
+        };
+        let at_least = |limit: Option<f64>, value: Option<f64>| match limit {
+            Some(limit) => value.is_some_and(|value| value >= limit),
+            None => true,
+        };
+        at_most(self.max_latency, item.latency)
+            && at_most(self.max_loss, item.loss)
+            && at_least(self.min_speed, item.speed)
+    }
+}
+
+/// 排序方式：延迟、丢包率从小到大，下载速度从大到小
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortBy {
+    Latency,
+    Speed,
+    Loss,
+}
+
+impl SortBy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "latency" | "delay" => Ok(SortBy::Latency),
+            "speed" => Ok(SortBy::Speed),
+            "loss" => Ok(SortBy::Loss),
+            _ => Err(format!(
+                "sort的值只能是latency、speed、loss，不能是：{}",
+                value
+            )),
+        }
+    }
+}
+
+/// 按测速结果排序（稳定排序，相同的数值保持原来的顺序），没有测速结果的数据排在最后
+pub fn sort_by_metric(datas: &mut [MyData], sort_by: SortBy) {
+    let compare = |a: Option<f64>, b: Option<f64>, ascending: bool| match (a, b) {
+        (Some(a), Some(b)) => match ascending {
+            true => a.total_cmp(&b),
+            false => b.total_cmp(&a),
+        },
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    };
+    datas.sort_by(|a, b| match sort_by {
+        SortBy::Latency => compare(a.latency, b.latency, true),
+        SortBy::Loss => compare(a.loss, b.loss, true),
+        SortBy::Speed => compare(a.speed, b.speed, false),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(addr: &str, latency: Option<f64>, loss: Option<f64>, speed: Option<f64>) -> MyData {
+        MyData {
+            addr: addr.to_string(),
+            latency,
+            loss,
+            speed,
+            ..Default::default()
+        }
+    }
+
+    fn addrs(datas: &[MyData]) -> Vec<&str> {
+        datas.iter().map(|item| item.addr.as_str()).collect()
+    }
+
+    #[test]
+    fn metric_filter_requires_metrics_when_set() {
+        let filter = MetricFilter {
+            max_latency: Some(200.0),
+            max_loss: Some(0.0),
+            min_speed: Some(5.0),
+        };
+        assert!(filter.matches(&data("a", Some(200.0), Some(0.0), Some(5.0))));
+        assert!(!filter.matches(&data("a", Some(200.1), Some(0.0), Some(5.0))));
+        assert!(!filter.matches(&data("a", Some(100.0), Some(0.1), Some(5.0))));
+        assert!(!filter.matches(&data("a", Some(100.0), Some(0.0), Some(4.9))));
+        // 没有测速结果的数据被过滤掉
+        assert!(!filter.matches(&data("a", None, Some(0.0), Some(5.0))));
+        // 没有条件时都保留
+        assert!(MetricFilter::default().matches(&data("a", None, None, None)));
+    }
+
+    #[test]
+    fn sorts_stably_with_missing_metrics_last() {
+        let mut datas = vec![
+            data("a", Some(150.0), None, Some(1.0)),
+            data("b", None, None, Some(9.0)),
+            data("c", Some(100.0), None, None),
+            data("d", Some(150.0), None, Some(9.0)),
+        ];
+        sort_by_metric(&mut datas, SortBy::Latency);
+        assert_eq!(addrs(&datas), ["c", "a", "d", "b"]);
+        // 下载速度从大到小，相同的速度保持原来的顺序
+        sort_by_metric(&mut datas, SortBy::Speed);
+        assert_eq!(addrs(&datas), ["d", "b", "a", "c"]);
+    }
+
+    #[test]
+    fn parses_sort_by() {
+        assert_eq!(SortBy::parse(" Delay "), Ok(SortBy::Latency));
+        assert_eq!(SortBy::parse("speed"), Ok(SortBy::Speed));
+        assert!(SortBy::parse("fast").is_err());
+    }
+
+    fn location(colo: &str, loc: &str) -> MyData {
+        MyData {
+            colo: Some(colo.to_string()),
+            loc: Some(loc.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn field_filter_matches_whole_value_ignoring_case() {
+        let mut filter = FieldFilter::default();
+        filter.add("hkg, NRT").unwrap();
+        assert!(filter.matches(Some("HKG")));
+        assert!(filter.matches(Some(" nrt ")));
+        // 完整匹配，不是包含
+        assert!(!filter.matches(Some("HKGX")));
+        assert!(!filter.matches(None));
+        // 正则表达式
+        filter.add("S.*").unwrap();
+        assert!(filter.matches(Some("SIN")));
+        assert!(FieldFilter::default().add("(").is_err());
+    }
+
+    #[test]
+    fn field_filter_exclusions() {
+        let mut filter = FieldFilter::default();
+        filter.add("!LAX,!s.*").unwrap();
+        // 只有排除条件时，其它的都保留
+        assert!(filter.matches(Some("HKG")));
+        assert!(filter.matches(None));
+        assert!(!filter.matches(Some("lax")));
+        assert!(!filter.matches(Some("SJC")));
+        // 排除条件优先
+        filter.add("LAX,HKG").unwrap();
+        assert!(!filter.matches(Some("LAX")));
+        assert!(filter.matches(Some("HKG")));
+        assert!(!filter.matches(Some("NRT")));
+    }
+
+    #[test]
+    fn location_filter_requires_all_fields() {
+        let mut filter = LocationFilter::default();
+        filter.colo.add("HKG,NRT").unwrap();
+        filter.loc.add("!JP").unwrap();
+        assert!(filter.matches(&location("HKG", "HK")));
+        assert!(!filter.matches(&location("NRT", "JP")));
+        assert!(!filter.matches(&location("LAX", "US")));
+    }
+}
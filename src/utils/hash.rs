@@ -0,0 +1,38 @@
+// FNV-1a 64位哈希的初始值和乘数
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 固定算法(FNV-1a 64位)的哈希值，不同版本的Rust编译后结果一样，可以用于随机数种子、保存到文件中
+/// 多个部分之间用0xff分隔(UTF-8字符串中不会出现)，避免 ["ab", "c"] 和 ["a", "bc"] 的哈希值一样
+pub fn stable_hash(parts: &[&str]) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut write = |byte: u8| {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    };
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            write(0xff);
+        }
+        part.bytes().for_each(&mut write);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_fnv1a_test_vectors() {
+        assert_eq!(stable_hash(&[""]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(stable_hash(&["a"]), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(stable_hash(&["foobar"]), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn separates_parts() {
+        assert_ne!(stable_hash(&["ab", "c"]), stable_hash(&["a", "bc"]));
+        assert_ne!(stable_hash(&["abc"]), stable_hash(&["ab", "c"]));
+    }
+}
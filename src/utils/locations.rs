@@ -0,0 +1,57 @@
+use crate::utils::file_data::FileData;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const LOCATIONS_PATH: &str = "data/locations.json";
+// 读取不到data/locations.json时，使用编译时内置的数据
+const BUILTIN_LOCATIONS: &str = include_str!("../../data/locations.json");
+
+#[allow(dead_code)]
+#[derive(Deserialize, Clone)]
+pub struct Location {
+    pub iata: String,   // 数据中心(3位字母)
+    pub cca2: String,   // 国家代码(2位字母)
+    pub region: String, // 地区
+    pub city: String,   // 城市
+    pub lat: f64,
+    pub lon: f64,
+}
+
+lazy_static! {
+    // 以iata(大写)为键的数据中心位置信息
+    static ref LOCATIONS: HashMap<String, Location> = load_locations();
+}
+
+fn load_locations() -> HashMap<String, Location> {
+    let content =
+        std::fs::read_to_string(LOCATIONS_PATH).unwrap_or_else(|_| BUILTIN_LOCATIONS.to_string());
+    let locations: Vec<Location> = serde_json::from_str(&content).unwrap_or_else(|e| {
+        eprintln!("解析 `{}` 失败，使用内置的数据：{}", LOCATIONS_PATH, e);
+        serde_json::from_str(BUILTIN_LOCATIONS).unwrap_or_default()
+    });
+    locations
+        .into_iter()
+        .map(|location| (location.iata.to_uppercase(), location))
+        .collect()
+}
+
+/// 根据iata查找数据中心的位置信息（忽略大小写）
+pub fn find_location(iata: &str) -> Option<&'static Location> {
+    LOCATIONS.get(&iata.trim().to_uppercase())
+}
+
+/// 根据数据中心(colo)，补全数据中缺少的国家代码、地区、城市
+pub fn fill_location(data: &mut FileData) {
+    let Some(location) = data.colo.as_deref().and_then(find_location) else {
+        return;
+    };
+    let fill = |field: &mut Option<String>, value: &str| {
+        if field.as_deref().is_none_or(|v| v.trim().is_empty()) {
+            *field = Some(value.to_string());
+        }
+    };
+    fill(&mut data.loc, &location.cca2);
+    fill(&mut data.region, &location.region);
+    fill(&mut data.city, &location.city);
+}
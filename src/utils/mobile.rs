@@ -0,0 +1,234 @@
+use crate::utils::{
+    strategy::NodePicker,
+    toml::{resolve_node, ResolvedNode, SelectedNode},
+};
+use base64::{engine::general_purpose::URL_SAFE, Engine};
+use serde_json::json;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MobileClient {
+    Loon,
+    Shadowrocket,
+}
+
+// 各客户端在tls参数上的差异，统一在这里处理
+struct Quirks {
+    sni_key: &'static str,                 // sni的参数名
+    insecure: &'static str,                // 跳过证书验证的写法
+    tls13: Option<&'static str>,           // 强制使用TLS1.3的写法
+    fingerprint_key: Option<&'static str>, // 指纹的参数名，None为不支持
+}
+
+impl MobileClient {
+    pub fn from_target(target: &str) -> Option<Self> {
+        match target {
+            "loon" => Some(MobileClient::Loon),
+            "shadowrocket" => Some(MobileClient::Shadowrocket),
+            _ => None,
+        }
+    }
+
+    fn quirks(&self) -> Quirks {
+        match self {
+            MobileClient::Loon => Quirks {
+                sni_key: "tls-name",
+                insecure: "skip-cert-verify=true",
+                tls13: None,
+                fingerprint_key: None,
+            },
+            MobileClient::Shadowrocket => Quirks {
+                sni_key: "peer",
+                insecure: "allowInsecure=1",
+                tls13: Some("tls13=1"),
+                fingerprint_key: Some("fp"),
+            },
+        }
+    }
+
+    // Loon不支持ss+v2ray-plugin，trojan只支持tls模式；Shadowrocket都支持
+    pub fn is_supported(&self) -> fn(&SelectedNode) -> bool {
+        match self {
+            MobileClient::Loon => |selected| match selected.node_type.as_str() {
+                "vless" | "vmess" => true,
+                "trojan" => selected.is_tls(),
+                _ => false,
+            },
+            MobileClient::Shadowrocket => |_| true,
+        }
+    }
+
+    // tls相关的参数，Loon用逗号分隔，Shadowrocket用&分隔
+    fn tls_params(&self, sni: &str, fingerprint: &str) -> Vec<String> {
+        let quirks = self.quirks();
+        let mut params = vec![
+            format!("{}={}", quirks.sni_key, sni),
+            quirks.insecure.to_string(),
+        ];
+        if let Some(tls13) = quirks.tls13 {
+            params.push(tls13.to_string());
+        }
+        if let Some(key) = quirks.fingerprint_key {
+            params.push(format!("{}={}", key, fingerprint));
+        }
+        params
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn build_mobile_line(
+    client: MobileClient,
+    nodes: &[SelectedNode], // usable_nodes返回的节点（已经剔除了客户端不支持的节点）
+    csv_tag: String,
+    csv_addr: String,
+    csv_port: u16,
+    uri_port: u16,
+    fingerprint: String,
+    http_ports: &[u16; 7],  // 非TLS 模式下，http的端口
+    https_ports: &[u16; 6], // TLS 模式下，https的端口
+    picker: &mut NodePicker,
+) -> (String, String) {
+    let Some(mut resolved) = resolve_node(
+        nodes,
+        &csv_tag,
+        &csv_addr,
+        csv_port,
+        uri_port,
+        http_ports,
+        https_ports,
+        picker,
+    ) else {
+        return ("".to_string(), "".to_string());
+    };
+
+    let line = match client {
+        MobileClient::Loon => {
+            // Loon的节点名称中，不能含有逗号和等号；返回的名称要和写入节点行的一致，策略组才能引用到
+            resolved.remarks = resolved.remarks.replace([',', '='], " ");
+            build_loon_line(client, &resolved, &fingerprint)
+        }
+        MobileClient::Shadowrocket => build_shadowrocket_link(client, &resolved, &fingerprint),
+    };
+    match line.is_empty() {
+        true => ("".to_string(), "".to_string()),
+        false => (resolved.remarks, line),
+    }
+}
+
+fn build_loon_line(client: MobileClient, resolved: &ResolvedNode, fingerprint: &str) -> String {
+    let node = &resolved.node;
+    let remarks = &resolved.remarks;
+    let head = match resolved.node_type.as_str() {
+        "vmess" => format!(
+            "{} = vmess,{},{},none,\"{}\",alterId=0",
+            remarks,
+            resolved.server,
+            resolved.port,
+            node.uuid.clone().unwrap_or_default()
+        ),
+        "vless" => format!(
+            "{} = vless,{},{},\"{}\"",
+            remarks,
+            resolved.server,
+            resolved.port,
+            node.uuid.clone().unwrap_or_default()
+        ),
+        "trojan" => format!(
+            "{} = trojan,{},{},\"{}\"",
+            remarks,
+            resolved.server,
+            resolved.port,
+            node.password.clone().unwrap_or_default()
+        ),
+        _ => return "".to_string(),
+    };
+
+    let mut params = vec![
+        "transport=ws".to_string(),
+        format!("path={}", node.path),
+        format!("host={}", node.host),
+    ];
+    if resolved.tls {
+        // trojan在Loon中默认就是tls，不需要over-tls参数
+        if resolved.node_type != "trojan" {
+            params.push("over-tls=true".to_string());
+        }
+        let sni = node.server_name.clone().unwrap_or(node.host.clone());
+        params.extend(client.tls_params(&sni, fingerprint));
+    }
+
+    format!("{},{}", head, params.join(","))
+}
+
+fn build_shadowrocket_link(
+    client: MobileClient,
+    resolved: &ResolvedNode,
+    fingerprint: &str,
+) -> String {
+    let node = &resolved.node;
+    let encoding_remarks = urlencoding::encode(&resolved.remarks);
+    let sni = node.server_name.clone().unwrap_or(node.host.clone());
+
+    if resolved.node_type == "ss" {
+        // v2ray-plugin的参数，以base64编码的json形式放在链接中
+        let plugin = json!({
+            "mode": "websocket",
+            "host": node.host,
+            "path": node.path,
+            "tls": resolved.tls,
+            "mux": false,
+            "peer": if resolved.tls { sni.as_str() } else { "" },
+            "allowInsecure": resolved.tls,
+        });
+        let user_info = URL_SAFE.encode(format!(
+            "none:{}",
+            node.password.clone().unwrap_or("none".to_string())
+        ));
+        return format!(
+            "ss://{}@{}:{}?v2ray-plugin={}#{}",
+            user_info,
+            resolved.server,
+            resolved.port,
+            URL_SAFE.encode(plugin.to_string()),
+            encoding_remarks
+        );
+    }
+
+    // vless、vmess的地址信息以base64编码，trojan直接使用密码
+    let authority = match resolved.node_type.as_str() {
+        "vless" | "vmess" => URL_SAFE.encode(format!(
+            "auto:{}@{}:{}",
+            node.uuid.clone().unwrap_or_default(),
+            resolved.server,
+            resolved.port
+        )),
+        "trojan" => format!(
+            "{}@{}:{}",
+            node.password.clone().unwrap_or_default(),
+            resolved.server,
+            resolved.port
+        ),
+        _ => return "".to_string(),
+    };
+
+    let mut params = vec![
+        format!("remarks={}", encoding_remarks),
+        "obfs=websocket".to_string(),
+        format!("obfsParam={}", urlencoding::encode(&node.host)),
+        format!("path={}", urlencoding::encode(&node.path)),
+    ];
+    if resolved.node_type == "vmess" {
+        params.push("alterId=0".to_string());
+    }
+    if resolved.tls {
+        params.push("tls=1".to_string());
+        params.extend(client.tls_params(&sni, fingerprint));
+    }
+
+    format!(
+        "{}://{}?{}#{}",
+        resolved.node_type,
+        authority,
+        params.join("&"),
+        encoding_remarks
+    )
+}
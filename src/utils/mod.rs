@@ -1,8 +1,29 @@
+pub mod cache;
+pub mod cidr;
 pub mod clash;
+pub mod conf_template;
+pub mod dns;
+pub mod encode;
+pub mod error;
+pub mod hash;
 pub mod indent;
+pub mod locations;
+pub mod mobile;
 pub mod qrcode;
+pub mod quota;
+pub mod rate_limit;
+pub mod quanx;
+pub mod reload;
 pub mod singbox;
+pub mod sources;
+pub mod strategy;
+pub mod sub_headers;
+pub mod surge;
 pub mod toml;
+pub mod users;
 pub mod v2ray;
+pub mod validate;
+pub mod xray;
 pub mod file_data;
+pub mod filter;
 pub mod net_data;
@@ -1,251 +1,215 @@
-use crate::utils::file_data::{ self, FileData, MyData };
-use reqwest;
-use csv::ReaderBuilder;
-use crossbeam_channel::unbounded;
-use std::{ error::Error, thread };
+use crate::utils::{
+    cache::{ self, CacheOptions, CachedResponse },
+    cidr::CidrOptions,
+    file_data::{ self, FileData, MyData },
+};
+use reqwest::{ header, redirect, StatusCode };
+use lazy_static::lazy_static;
+use std::{ sync::Arc, time::Duration };
 
-// 同步函数中使用异步，使用 std::thread::spawn 在另一个线程中运行异步代码
-fn read_csv_from_url(
-    url: &str,
-    default_port: u16
-) -> Result<Vec<FileData>, Box<dyn Error + Send + Sync>> {
-    let (sender, receiver) = unbounded();
-    let url_copy = url.to_string();
-
-    thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        let result = rt.block_on(async {
-            let response = reqwest::get(&url_copy).await?;
-            if !response.status().is_success() {
-                return Err(format!("Failed to fetch CSV from URL: {}", response.status()).into());
-            }
-            let body = response.text().await?;
-            let mut rdr = ReaderBuilder::new().from_reader(body.as_bytes());
-            let headers = rdr.headers()?; // 读取并忽略头部
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5); // 连接超时
+const READ_TIMEOUT: Duration = Duration::from_secs(15); // 读取数据超时(两次读取之间的间隔)
+const MAX_RETRIES: u32 = 2; // 请求失败后，最多重试几次
+const RETRY_BACKOFF: Duration = Duration::from_millis(500); // 第一次重试前的等待时间，之后每次翻倍
+const MAX_BODY_SIZE: usize = 20 * 1024 * 1024; // 最多下载20MB的数据
+const MAX_REDIRECTS: usize = 5; // 最多重定向几次
+const TOTAL_TIMEOUT: Duration = Duration::from_secs(60); // 获取一个链接(包括重试)最多用多长时间
 
-            // csv文件列名的映射关系，特别是奇奇怪怪的列名
-            let field_map = file_data::create_field_map();
-
-            // 尝试从标题中查找列索引(下标)
-            let find_index = |key: &str| {
-                field_map.get(key).and_then(|candidates|
-                    candidates.iter().find_map(|&field|
-                        headers.iter().position(
-                            |header| header.trim().to_lowercase() == field.trim().to_lowercase() // 忽略字段中的大小写
-                        )
-                    )
-                )
-            };
-            // 找csv标题的列名跟向量中哪个元素对应 => 在哪个索引(下标)中
-            let addr_index = find_index("addr");
-            let port_index = find_index("port");
-            let colo_index = find_index("colo");
-            let loc_index = find_index("loc");
-            let region_index = find_index("region");
-            let city_index = find_index("city");
+lazy_static! {
+    // 所有请求共用一个Client，复用连接池
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .read_timeout(READ_TIMEOUT)
+        .redirect(
+            // 只允许同一个域名内的重定向，避免绕过config.toml中允许的链接
+            redirect::Policy::custom(|attempt| {
+                let same_host = attempt.previous()
+                    .first()
+                    .is_some_and(|first| first.host_str() == attempt.url().host_str());
+                match same_host && attempt.previous().len() < MAX_REDIRECTS {
+                    true => attempt.follow(),
+                    false => attempt.stop(),
+                }
+            })
+        )
+        .user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("Failed to build HTTP client");
+}
 
-            // 1. 将 CSV 记录转换为结构体实例，并收集到向量
-            let mut records: Vec<FileData> = Vec::new();
+// 请求失败的原因，retryable表示是否值得重试(超时、连接失败、5xx、429)
+struct FetchError {
+    message: String,
+    retryable: bool,
+}
 
-            for record in rdr.records() {
-                let record = record?;
+impl FetchError {
+    fn retryable(message: String) -> Self {
+        FetchError { message, retryable: true }
+    }
 
-                // 获取`IP地址`字段的值
-                let addr_column = addr_index.and_then(|index| record.get(index)).unwrap_or("");
+    fn fatal(message: String) -> Self {
+        FetchError { message, retryable: false }
+    }
+}
 
-                if addr_column.is_empty() {
-                    continue;
-                }
+// 请求的结果，有缓存时，服务器可能返回304(内容没有更新)
+enum Fetched {
+    Modified(CachedResponse),
+    NotModified,
+}
 
-                // 获取`端口`字段的值
-                let port_column: u16 = port_index
-                    .and_then(|index| record.get(index).and_then(|val| val.parse::<u16>().ok())) // 显示转换
-                    .unwrap_or(default_port); // 默认为`default_port`
+// 请求一次，读取响应的内容，超过MAX_BODY_SIZE就放弃；有缓存时，带上ETag、Last-Modified确认是否有更新
+async fn fetch_once(url: &str, cached: Option<&CachedResponse>) -> Result<Fetched, FetchError> {
+    let mut request = HTTP_CLIENT.get(url);
+    if let Some(cached) = cached {
+        if let Some(etag) = &cached.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let mut response = request
+        .send().await
+        .map_err(|e| FetchError::retryable(format!("请求失败：{}", e)))?;
 
-                // 定义一个闭包来处理列的提取逻辑(只支持String数据类型的数据提取)
-                let get_column_string = |index: Option<usize>| {
-                    index
-                        .and_then(|idx| record.get(idx).and_then(|val| val.parse().ok())) // 隐式转换
-                        .unwrap_or_else(|| "".to_string()) // 默认为空字符串
-                };
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED && cached.is_some() {
+        return Ok(Fetched::NotModified);
+    }
+    if !status.is_success() {
+        let message = format!("HTTP状态码：{}", status);
+        return match status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            true => Err(FetchError::retryable(message)),
+            false => Err(FetchError::fatal(message)),
+        };
+    }
+    if response.content_length().is_some_and(|len| len > (MAX_BODY_SIZE as u64)) {
+        return Err(FetchError::fatal(format!("数据超过{}字节", MAX_BODY_SIZE)));
+    }
 
-                // 使用闭包提取列数据，没有找到对应的列时，返回空字符串
-                let colo_column = get_column_string(colo_index);
-                let loc_column = get_column_string(loc_index);
-                let region_column = get_column_string(region_index);
-                let city_column = get_column_string(city_index);
+    // 分块读取，没有Content-Length的响应，也能限制大小
+    let mut body: Vec<u8> = Vec::new();
+    while
+        let Some(chunk) = response
+            .chunk().await
+            .map_err(|e| FetchError::retryable(format!("读取数据失败：{}", e)))?
+    {
+        if body.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(FetchError::fatal(format!("数据超过{}字节", MAX_BODY_SIZE)));
+        }
+        body.extend_from_slice(&chunk);
+    }
 
-                let data = FileData {
-                    addr: addr_column.to_string(),
-                    port: Some(port_column),
-                    colo: Some(colo_column),
-                    loc: Some(loc_column),
-                    region: Some(region_column),
-                    city: Some(city_column),
-                };
-                records.push(data);
-            }
-            Ok(records) // 2. 返回向量，这个类似return
-        });
-        sender.send(result).unwrap(); // 3.  这里将这个 result 发送到通道中
-    });
+    let header_value = |name: header::HeaderName| {
+        response.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string)
+    };
+    Ok(
+        Fetched::Modified(
+            CachedResponse::new(
+                url,
+                String::from_utf8_lossy(&body).into_owned(),
+                header_value(header::ETAG),
+                header_value(header::LAST_MODIFIED)
+            )
+        )
+    )
+}
 
-    // 4. 接收通道中的结果
-    receiver.recv().unwrap()
+// 请求网络文件，失败时按指数退避重试，超过TOTAL_TIMEOUT就放弃(避免服务器很慢地发送数据，一直占用转换的名额)
+async fn fetch_with_retry(url: &str, cached: Option<&CachedResponse>) -> Result<Fetched, String> {
+    tokio::time::timeout(TOTAL_TIMEOUT, retry_fetch(url, cached)).await
+        .unwrap_or_else(|_| Err(format!("超过{}秒没有获取完数据", TOTAL_TIMEOUT.as_secs())))
 }
 
-fn read_txt_from_url(
-    url: &str,
-    default_port: u16
-) -> Result<Vec<FileData>, Box<dyn Error + Send + Sync>> {
-    let (sender, receiver) = unbounded();
-    let url_copy = url.to_string();
-    let mut seen_lines: Vec<String> = Vec::new();
-    thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        let result = rt.block_on(async {
-            let response = reqwest::get(&url_copy).await?;
-            if !response.status().is_success() {
-                return Err(format!("Failed to fetch txt from URL: {}", response.status()).into());
+async fn retry_fetch(url: &str, cached: Option<&CachedResponse>) -> Result<Fetched, String> {
+    let mut attempt = 0;
+    loop {
+        match fetch_once(url, cached).await {
+            Ok(fetched) => {
+                return Ok(fetched);
             }
-            let body = response.text().await?;
-            let mut records: Vec<FileData> = Vec::new();
-            for line in body.lines() {
-                let trimmed_line = line.trim().to_string();
-
-                let contains_bool =
-                    trimmed_line.contains("/") || seen_lines.contains(&trimmed_line);
-                if trimmed_line.is_empty() || contains_bool {
-                    continue;
-                }
-
-                // 提取地址和端口
-                let parts: Vec<String> = if
-                    let Some(captures) = file_data::IPV6_PORT_COMMA_REGEX.captures(&trimmed_line)
-                {
-                    // 判断是否为 "IPv6, PORT" 格式(逗号左右，可以0个以上的空格)
-                    let ipv6 = captures.get(1).map_or("", |m| m.as_str());
-                    let port = captures.get(2).map_or("", |m| m.as_str());
-                    vec![format!("[{}]", ipv6), port.to_string()]
-                } else if file_data::IPV6_PORT_SPACE_REGEX.is_match(&trimmed_line) {
-                    // 判断是否为 "IPv6 PORT" 地址
-                    file_data::SPACE_REGEX
-                        .splitn(&trimmed_line, 2)
-                        .map(|s| {
-                            let str_s = s.to_string();
-                            let colon_count = str_s
-                                .chars()
-                                .filter(|&c| c == ':')
-                                .count();
-                            if colon_count > 1 {
-                                if str_s.starts_with('[') && str_s.ends_with(']') {
-                                    str_s // 已经有方括号，直接返回
-                                } else {
-                                    format!("[{}]", str_s) // 添加方括号
-                                }
-                            } else {
-                                str_s // 不满足条件，直接返回
-                            }
-                        })
-                        .collect()
-                } else if
-                    let Some(captures) = file_data::IPV6_PORT_BRACKET_REGEX.captures(&trimmed_line)
-                {
-                    // 判断是否为 "[IPv6]:PORT" 格式
-                    vec![
-                        format!("[{}]", captures.get(1).unwrap().as_str().to_string()),
-                        captures.get(2).unwrap().as_str().to_string()
-                    ]
-                } else if
-                    let Some(captures) = file_data::IPV4_PORT_SPACE_REGEX.captures(&trimmed_line)
-                {
-                    // 判断是否为 "IPv4 PORT" 格式
-                    vec![
-                        captures.get(1).unwrap().as_str().to_string(),
-                        captures.get(2).unwrap().as_str().to_string()
-                    ]
-                } else if
-                    trimmed_line.contains(':') &&
-                    trimmed_line
-                        .chars()
-                        .filter(|&c| c == ':')
-                        .count() == 1
-                {
-                    // 判断是否为 "IPv4:PORT" 或 "Domain:PORT" 格式
-                    trimmed_line
-                        .splitn(2, ':')
-                        .map(|s| s.to_string())
-                        .collect()
-                } else if trimmed_line.contains(", ") {
-                    // 判断是否为 "IPv4, PORT" 、"[IPv6], PORT"、" "Domain, PORT" 格式
-                    trimmed_line
-                        .splitn(2, ", ")
-                        .map(|s| s.to_string())
-                        .collect()
-                } else if trimmed_line.contains(',') {
-                    // 判断是否为 "IPv4,PORT" 、"[IPv6],PORT"、" "Domain,PORT" 格式
-                    trimmed_line
-                        .splitn(2, ',')
-                        .map(|s| s.to_string())
-                        .collect()
-                } else if file_data::SPACE_REGEX.is_match(&trimmed_line) {
-                    // 判断是否为 "[IPv6] PORT" 或 "Domain PORT" 格式
-                    let value = file_data::SPACE_REGEX
-                        .splitn(&trimmed_line, 2)
-                        .map(|s| s.to_string())
-                        .collect();
-                    value
-                } else {
-                    // 匹配 "IPv4"、"[ipv6]"、"Domain" 格式
-                    vec![trimmed_line.to_string(), default_port.to_string()]
-                };
-
-                if parts.len() == 2 {
-                    let final_line = format!("{}:{}", parts[0], parts[1]);
-                    if !seen_lines.contains(&final_line) && !parts[0].is_empty() {
-                        let data = FileData {
-                            addr: parts[0].clone(),
-                            port: parts[1].parse::<u16>().ok(),
-                            ..Default::default() // 其它字段不管，使用默认值
-                        };
-                        seen_lines.push(final_line);
-                        records.push(data);
-                    }
-                } else {
-                    println!("不支持提取 `{}` 的地址和端口！", trimmed_line);
-                }
+            Err(e) if e.retryable && attempt < MAX_RETRIES => {
+                let backoff = RETRY_BACKOFF * (2u32).pow(attempt);
+                attempt += 1;
+                eprintln!("获取 `{}` 失败（{}），{:?}后第{}次重试", url, e.message, backoff, attempt);
+                tokio::time::sleep(backoff).await;
             }
-            Ok(records)
-        });
-        sender.send(result).unwrap();
-    });
+            Err(e) => {
+                return Err(e.message);
+            }
+        }
+    }
+}
+
+// 获取网络文件的内容：缓存没有过期就直接使用；过期了就向服务器确认是否有更新；获取失败时，使用过期的缓存
+async fn fetch_text(url: &str, cache_options: &CacheOptions) -> Result<Arc<str>, String> {
+    let cached = cache::get(url, cache_options);
+    if let Some(cached) = cached.as_ref().filter(|cached| cached.is_fresh(cache_options.ttl)) {
+        return Ok(cached.body.clone());
+    }
 
-    receiver.recv().unwrap()
+    match (fetch_with_retry(url, cached.as_ref()).await, cached) {
+        (Ok(Fetched::Modified(response)), _) => {
+            let body = response.body.clone();
+            cache::put(response, cache_options);
+            Ok(body)
+        }
+        (Ok(Fetched::NotModified), Some(cached)) => {
+            let cached = cached.revalidated();
+            let body = cached.body.clone();
+            cache::put(cached, cache_options);
+            Ok(body)
+        }
+        (Err(e), Some(cached)) => {
+            eprintln!("获取 `{}` 失败（{}），使用之前缓存的内容", url, e);
+            Ok(cached.body)
+        }
+        (Ok(Fetched::NotModified), None) => Err("服务器返回304，但没有缓存".to_string()),
+        (Err(e), None) => Err(e),
+    }
 }
 
-fn process_url(
+async fn process_url(
     url: &str,
-    default_port: u16
-) -> Result<Vec<FileData>, Box<dyn Error + Send + Sync>> {
-    match url {
-        url if url.to_lowercase().ends_with(".txt") => read_txt_from_url(url, default_port),
-        url if url.to_lowercase().ends_with(".csv") => read_csv_from_url(url, default_port),
-        _ => Err(format!("{} 不是 txt 或 csv 文件的链接", url).into()),
+    default_port: u16,
+    count: usize,
+    cidr: Option<&CidrOptions>,
+    cache_options: &CacheOptions
+) -> Result<Vec<FileData>, String> {
+    // 先检查文件类型，不支持的链接就不用下载了
+    let Some(file_type) = file_data::file_type(url) else {
+        return Err(format!("{} 不是 txt、csv、json、jsonl、yaml 文件的链接", url));
+    };
+
+    let body = fetch_text(url, cache_options).await?;
+    match file_type {
+        "csv" => file_data::parse_csv(body.as_bytes(), default_port).map_err(|e| e.to_string()),
+        "txt" => {
+            let mut expander = cidr.map(CidrOptions::expander);
+            Ok(file_data::parse_txt(&body, default_port, expander.as_mut(), count))
+        }
+        _ =>
+            file_data::parse_structured(file_type, &body, default_port).map_err(|e| e.to_string()),
     }
 }
 
 #[allow(dead_code)]
-pub fn process_network_data(
+pub async fn process_network_data(
     field_column: &str,
     default_port: u16,
     count: usize,
-    url: &str
-) -> Vec<MyData> {
+    url: &str,
+    cidr: Option<&CidrOptions>, // 有值时，将txt文件中的CIDR网段展开为IP地址
+    cache_options: &CacheOptions // 网络数据的缓存选项
+) -> Result<Vec<MyData>, String> {
     let mut results: Vec<MyData> = Vec::new(); // 存储结果
     let mut seen_addr = Vec::new();
 
-    if url.to_lowercase().starts_with("https://") {
-        match process_url(url, default_port) {
+    let lower_url = url.to_lowercase();
+    if lower_url.starts_with("http://") || lower_url.starts_with("https://") {
+        match process_url(url, default_port, count, cidr, cache_options).await {
             Ok(data) => {
                 for item in &data {
                     let addr: String = item.addr.clone();
@@ -273,6 +237,13 @@ pub fn process_network_data(
                         addr: addr.clone(),
                         port: Some(port),
                         alias: alias_prefix,
+                        colo: item.colo.clone(),
+                        loc: item.loc.clone(),
+                        region: item.region.clone(),
+                        city: item.city.clone(),
+                        latency: item.latency,
+                        loss: item.loss,
+                        speed: item.speed,
                     };
 
                     // 如果结果数量小于指定的数量，则添加数据，否则就返回，避免无意义的IO操作(读取数据)
@@ -283,9 +254,12 @@ pub fn process_network_data(
                     }
                 }
             }
-            Err(e) => eprintln!("Error: {}", e),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return Err(format!("{}：{}", url, e));
+            }
         }
     }
 
-    results
+    Ok(results)
 }
@@ -0,0 +1,80 @@
+use crate::utils::{
+    conf_template::add_conf_template,
+    strategy::NodePicker,
+    toml::{resolve_node, SelectedNode},
+};
+
+#[allow(clippy::too_many_arguments)]
+pub fn build_quanx_line(
+    nodes: &[SelectedNode], // usable_nodes返回的节点（已经剔除了客户端不支持的节点）
+    csv_tag: String,
+    csv_addr: String,
+    csv_port: u16,
+    uri_port: u16,
+    http_ports: &[u16; 7],  // 非TLS 模式下，http的端口
+    https_ports: &[u16; 6], // TLS 模式下，https的端口
+    picker: &mut NodePicker,
+) -> (String, String) {
+    let Some(resolved) = resolve_node(
+        nodes,
+        &csv_tag,
+        &csv_addr,
+        csv_port,
+        uri_port,
+        http_ports,
+        https_ports,
+        picker,
+    ) else {
+        return ("".to_string(), "".to_string());
+    };
+
+    // Quantumult X的tag值中，不能含有逗号
+    let remarks = resolved.remarks.replace(',', " ");
+    let node = resolved.node;
+
+    // vless、vmess、ss使用的是method+password，trojan只有password
+    let (scheme, credential) = match resolved.node_type.as_str() {
+        "vless" => (
+            "vless",
+            format!("method=none, password={}", node.uuid.unwrap_or_default()),
+        ),
+        "vmess" => (
+            "vmess",
+            format!("method=none, password={}", node.uuid.unwrap_or_default()),
+        ),
+        "trojan" => (
+            "trojan",
+            format!("password={}", node.password.unwrap_or_default()),
+        ),
+        "ss" => (
+            "shadowsocks",
+            format!(
+                "method=none, password={}",
+                node.password.unwrap_or("none".to_string())
+            ),
+        ),
+        _ => return ("".to_string(), "".to_string()),
+    };
+
+    let obfs = match resolved.tls {
+        true => format!(
+            "obfs=wss, obfs-host={}, obfs-uri={}, tls-host={}, tls-verification=false",
+            node.host,
+            node.path,
+            node.server_name.unwrap_or(node.host.clone())
+        ),
+        false => format!("obfs=ws, obfs-host={}, obfs-uri={}", node.host, node.path),
+    };
+
+    let line = format!(
+        "{}={}:{}, {}, {}, fast-open=false, udp-relay=false, tag={}",
+        scheme, resolved.server, resolved.port, credential, obfs, remarks
+    );
+
+    (remarks, line)
+}
+
+/// 将节点插入模板的[server_local]中，并将[policy]中的{all}替换为节点名称
+pub fn add_quanx_template(template: &str, proxies_vec: Vec<(String, String)>) -> String {
+    add_conf_template(template, "[server_local]", "[policy]", proxies_vec)
+}
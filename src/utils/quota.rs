@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// 某个token在某一天(UTC)的请求次数
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct DailyCount {
+    day: u64, // 从1970-01-01开始的天数
+    count: u32,
+}
+
+/// 每个token每天的请求次数，以内存中的次数为准，定时(flush)保存到文件中，重启后继续计数
+pub struct QuotaStore {
+    path: Option<PathBuf>,
+    counts: Mutex<HashMap<String, DailyCount>>,
+    dirty: AtomicBool, // 上次保存后，次数是否有变化
+}
+
+impl QuotaStore {
+    /// 从文件中读取请求次数，文件不存在或解析失败时从0开始计数
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let counts = path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|content| match serde_json::from_str(&content) {
+                Ok(counts) => Some(counts),
+                Err(e) => {
+                    eprintln!("解析请求次数的文件失败，从0开始计数：{}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        QuotaStore {
+            path,
+            counts: Mutex::new(counts),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// 记录一次请求，超出每天的次数时返回距离明天(UTC)还有多少秒
+    pub fn try_acquire(&self, token: &str, limit: u32) -> Result<(), u64> {
+        self.try_acquire_at(token, limit, now())
+    }
+
+    fn try_acquire_at(&self, token: &str, limit: u32, now: u64) -> Result<(), u64> {
+        let today = now / SECONDS_PER_DAY;
+        let mut counts = self.counts.lock().unwrap();
+        // 文件中不保存token本身，只保存token的哈希值
+        let entry = counts.entry(token_key(token)).or_insert(DailyCount {
+            day: today,
+            count: 0,
+        });
+        if entry.day != today {
+            *entry = DailyCount {
+                day: today,
+                count: 0,
+            };
+        }
+        if entry.count >= limit {
+            return Err((today + 1) * SECONDS_PER_DAY - now);
+        }
+        entry.count += 1;
+        // 只保留今天的记录，避免文件越来越大
+        counts.retain(|_, count| count.day == today);
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// 退回一次请求(请求没有成功生成订阅时)，只退回今天的次数
+    pub fn refund(&self, token: &str) {
+        self.refund_at(token, now())
+    }
+
+    fn refund_at(&self, token: &str, now: u64) {
+        let today = now / SECONDS_PER_DAY;
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(entry) = counts.get_mut(&token_key(token)) {
+            if entry.day == today && entry.count > 0 {
+                entry.count -= 1;
+                self.dirty.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// 次数有变化时保存到文件中，会阻塞(写文件)，请在spawn_blocking中调用
+    pub fn flush(&self) {
+        let Some(path) = self.path.as_ref() else {
+            return;
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        // 复制一份再写文件，写文件时不占用锁
+        let counts = self.counts.lock().unwrap().clone();
+        // 先写入临时文件再重命名，避免写到一半时退出导致文件损坏
+        let tmp_path = path.with_extension("tmp");
+        let content = serde_json::to_string(&counts).unwrap_or_default();
+        let result =
+            std::fs::write(&tmp_path, content).and_then(|_| std::fs::rename(&tmp_path, path));
+        if let Err(e) = result {
+            eprintln!("保存请求次数到 `{}` 失败：{}", path.display(), e);
+            self.dirty.store(true, Ordering::Relaxed); // 下次再试
+        }
+    }
+}
+
+// 使用SHA-256，不同版本的Rust编译后，同一个token的哈希值不变
+fn token_key(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// 已经扣除的一次请求，生成订阅失败时(drop时还没有调用commit)自动退回
+pub struct QuotaCharge<'a> {
+    store: &'a QuotaStore,
+    token: String,
+    committed: bool,
+}
+
+impl<'a> QuotaCharge<'a> {
+    /// 扣除一次请求，超出每天的次数时返回距离明天(UTC)还有多少秒
+    pub fn acquire(store: &'a QuotaStore, token: &str, limit: u32) -> Result<Self, u64> {
+        store.try_acquire(token, limit)?;
+        Ok(QuotaCharge {
+            store,
+            token: token.to_string(),
+            committed: false,
+        })
+    }
+
+    /// 订阅已经生成，不再退回
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for QuotaCharge<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.store.refund(&self.token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 20000 * SECONDS_PER_DAY; // 某一天的0点
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("quota-{}-{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn rejects_over_limit_until_next_day() {
+        let store = QuotaStore::open(None);
+        assert_eq!(store.try_acquire_at("a", 2, DAY + 10), Ok(()));
+        assert_eq!(store.try_acquire_at("a", 2, DAY + 20), Ok(()));
+        // 返回距离明天还有多少秒
+        assert_eq!(
+            store.try_acquire_at("a", 2, DAY + 30),
+            Err(SECONDS_PER_DAY - 30)
+        );
+        // 其它token不受影响
+        assert_eq!(store.try_acquire_at("b", 2, DAY + 30), Ok(()));
+        // 第二天重新计数
+        let tomorrow = DAY + SECONDS_PER_DAY;
+        assert_eq!(store.try_acquire_at("a", 2, tomorrow), Ok(()));
+        assert_eq!(store.try_acquire_at("a", 2, tomorrow), Ok(()));
+        assert!(store.try_acquire_at("a", 2, tomorrow).is_err());
+    }
+
+    #[test]
+    fn refund_only_today() {
+        let store = QuotaStore::open(None);
+        assert_eq!(store.try_acquire_at("a", 1, DAY), Ok(()));
+        store.refund_at("a", DAY);
+        assert_eq!(store.try_acquire_at("a", 1, DAY), Ok(()));
+        // 昨天的次数不会退回到今天
+        store.refund_at("a", DAY + SECONDS_PER_DAY);
+        assert!(store.try_acquire_at("a", 1, DAY).is_err());
+        // 没有记录的token不会变成负数
+        store.refund_at("c", DAY);
+        assert_eq!(store.try_acquire_at("c", 1, DAY), Ok(()));
+    }
+
+    #[test]
+    fn charge_refunds_on_drop_unless_committed() {
+        let store = QuotaStore::open(None);
+        drop(QuotaCharge::acquire(&store, "a", 1).unwrap());
+        // 没有commit，退回后还可以请求
+        let charge = QuotaCharge::acquire(&store, "a", 1).unwrap();
+        charge.commit();
+        assert!(QuotaCharge::acquire(&store, "a", 1).is_err());
+    }
+
+    #[test]
+    fn counts_survive_reopen() {
+        let path = temp_file("reopen");
+        let store = QuotaStore::open(Some(path.clone()));
+        assert_eq!(store.try_acquire("a", 2), Ok(()));
+        // 没有flush之前不写文件
+        assert!(!path.exists());
+        store.flush();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("\"a\""), "文件中不能保存token本身");
+
+        let store = QuotaStore::open(Some(path.clone()));
+        assert_eq!(store.try_acquire("a", 2), Ok(()));
+        assert!(store.try_acquire("a", 2).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reopened_counts_reset_on_new_day() {
+        let path = temp_file("new-day");
+        let store = QuotaStore::open(Some(path.clone()));
+        assert_eq!(store.try_acquire_at("a", 1, DAY), Ok(()));
+        store.flush();
+
+        let store = QuotaStore::open(Some(path.clone()));
+        assert!(store.try_acquire_at("a", 1, DAY).is_err());
+        assert_eq!(store.try_acquire_at("a", 1, DAY + SECONDS_PER_DAY), Ok(()));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// 最多记录多少个键，超过时先清理已经补满令牌的桶，还是超过就删除最久没有请求的桶，避免大量不同的IP占用内存
+const MAX_BUCKETS: usize = 4096;
+
+// 一个令牌桶，每次请求消耗一个令牌，令牌按固定的速度补充，最多burst个
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant, // 最后一次请求的时间
+}
+
+/// 按键(IP或token)限制请求的速度，每个键一个令牌桶
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+    max_buckets: usize,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::with_capacity(MAX_BUCKETS)
+    }
+}
+
+impl RateLimiter {
+    /// 最多记录max_buckets个键
+    pub fn with_capacity(max_buckets: usize) -> Self {
+        RateLimiter {
+            buckets: Mutex::new(HashMap::new()),
+            max_buckets: max_buckets.max(1),
+        }
+    }
+
+    /// 消耗一个令牌，per_minute为0表示不限制，没有令牌时返回多少秒后再试
+    pub fn check(&self, key: &str, per_minute: u32, burst: u32) -> Result<(), u64> {
+        self.check_at(key, per_minute, burst, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, per_minute: u32, burst: u32, now: Instant) -> Result<(), u64> {
+        if per_minute == 0 {
+            return Ok(());
+        }
+        let rate = per_minute as f64 / 60.0; // 每秒补充的令牌数
+        let burst = burst.max(1) as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if !buckets.contains_key(key) && buckets.len() >= self.max_buckets {
+            buckets.retain(|_, bucket| refill(bucket, now, rate, burst) < burst);
+            // 还是太多时，删除最久没有请求的桶
+            while buckets.len() >= self.max_buckets {
+                let oldest = buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(key, _)| key.clone());
+                match oldest {
+                    Some(oldest) => buckets.remove(&oldest),
+                    None => break,
+                };
+            }
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(TokenBucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = refill(bucket, now, rate, burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / rate);
+            Err(wait.as_secs_f64().ceil() as u64)
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+}
+
+// 从上次更新到现在，桶中应该有的令牌数
+fn refill(bucket: &TokenBucket, now: Instant, rate: f64, burst: f64) -> f64 {
+    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+    (bucket.tokens + elapsed * rate).min(burst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_after_burst_with_retry_after() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at("1.1.1.1", 60, 3, now), Ok(()));
+        }
+        // 每分钟60次，1秒补充一个令牌
+        assert_eq!(limiter.check_at("1.1.1.1", 60, 3, now), Err(1));
+        // 其它键不受影响
+        assert_eq!(limiter.check_at("2.2.2.2", 60, 3, now), Ok(()));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        assert_eq!(limiter.check_at("a", 6, 1, now), Ok(()));
+        // 每分钟6次，10秒补充一个令牌
+        assert_eq!(limiter.check_at("a", 6, 1, now), Err(10));
+        assert_eq!(
+            limiter.check_at("a", 6, 1, now + Duration::from_secs(4)),
+            Err(6)
+        );
+        assert_eq!(
+            limiter.check_at("a", 6, 1, now + Duration::from_secs(10)),
+            Ok(())
+        );
+        // 补充的令牌不会超过burst
+        let later = now + Duration::from_secs(3600);
+        assert_eq!(limiter.check_at("a", 6, 1, later), Ok(()));
+        assert!(limiter.check_at("a", 6, 1, later).is_err());
+    }
+
+    #[test]
+    fn zero_per_minute_is_unlimited() {
+        let limiter = RateLimiter::default();
+        for _ in 0..100 {
+            assert_eq!(limiter.check("a", 0, 1), Ok(()));
+        }
+        assert_eq!(limiter.len(), 0);
+    }
+
+    #[test]
+    fn evicts_least_recently_seen_when_full() {
+        let limiter = RateLimiter::with_capacity(3);
+        let now = Instant::now();
+        // 每个键都把令牌用完，不能靠"补满令牌"清理
+        for (i, key) in ["a", "b", "c"].iter().enumerate() {
+            let at = now + Duration::from_millis(i as u64);
+            assert_eq!(limiter.check_at(key, 1, 1, at), Ok(()));
+        }
+        let at = now + Duration::from_millis(10);
+        assert_eq!(limiter.check_at("d", 1, 1, at), Ok(()));
+        assert_eq!(limiter.len(), 3);
+        // a最久没有请求，被删除后重新开始计数；d还在，仍然被限制
+        assert_eq!(limiter.check_at("a", 1, 1, at), Ok(()));
+        assert!(limiter.check_at("d", 1, 1, at).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+/// 缓存配置文件/模板解析后的结果，文件修改后重新加载；加载失败时继续使用上一次的结果
+pub struct Watched<T> {
+    path: &'static str,
+    parse: fn(&str) -> Result<T, String>,
+    state: RwLock<WatchedState<T>>,
+}
+
+struct WatchedState<T> {
+    checked: bool,                // 是否已经读取过文件
+    modified: Option<SystemTime>, // 上一次读取时(包括解析失败)，文件的修改时间
+    value: Option<Arc<T>>,        // 最后一次成功解析的结果
+    error: Option<String>,        // 从未解析成功时，最后一次的错误信息
+}
+
+impl<T> Watched<T> {
+    pub fn new(path: &'static str, parse: fn(&str) -> Result<T, String>) -> Self {
+        let watched = Watched {
+            path,
+            parse,
+            state: RwLock::new(WatchedState {
+                checked: false,
+                modified: None,
+                value: None,
+                error: None,
+            }),
+        };
+        watched.reload_if_changed();
+        watched
+    }
+
+    /// 获取最后一次成功解析的结果；文件不存在返回Ok(None)，文件存在却从未解析成功返回Err
+    pub fn load(&self) -> Result<Option<Arc<T>>, String> {
+        let state = self.state.read().unwrap();
+        match (&state.value, &state.error) {
+            (Some(value), _) => Ok(Some(value.clone())),
+            (None, Some(e)) => Err(format!("`{}` {}", self.path, e)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// 文件的修改时间有变化时，重新读取并解析；解析失败后，文件没有再修改就不会重试
+    pub fn reload_if_changed(&self) {
+        let modified = std::fs::metadata(self.path)
+            .and_then(|meta| meta.modified())
+            .ok();
+        let (checked, last_modified) = {
+            let state = self.state.read().unwrap();
+            (state.checked, state.modified)
+        };
+        if checked && modified == last_modified {
+            return;
+        }
+
+        let result = match modified {
+            Some(_) => std::fs::read_to_string(self.path)
+                .map_err(|e| e.to_string())
+                .and_then(|content| (self.parse)(&content)),
+            None => Err("文件不存在".to_string()),
+        };
+
+        let mut state = self.state.write().unwrap();
+        state.checked = true;
+        state.modified = modified;
+        match result {
+            Ok(value) => {
+                if last_modified.is_some() {
+                    println!("已重新加载 `{}`", self.path);
+                }
+                state.value = Some(Arc::new(value));
+                state.error = None;
+            }
+            Err(e) => match state.value {
+                Some(_) => eprintln!("重新加载 `{}` 失败，继续使用上一次的内容：{}", self.path, e),
+                None => {
+                    eprintln!("加载 `{}` 失败：{}", self.path, e);
+                    // 文件不存在不算错误，由使用者决定是否必须要有这个文件
+                    state.error = modified.map(|_| e);
+                }
+            },
+        }
+    }
+}
@@ -1,121 +1,84 @@
-use crate::utils::toml::{selecting_config_of_node, Proxy};
-use rand::seq::SliceRandom;
+use crate::utils::{
+    strategy::NodePicker,
+    toml::{resolve_node, SelectedNode},
+};
 use serde_json::{json, Value as JsonValue};
 
+#[allow(clippy::too_many_arguments)]
 pub fn build_singbox_json(
-    toml_proxies: &Proxy,
+    nodes: &[SelectedNode], // usable_nodes返回的节点（已经剔除了客户端不支持的节点）
     csv_tag: String,
     csv_addr: String,
     csv_port: u16,
     uri_port: u16,
-    uri_userid: u8,
-    uri_proxy_type: String,
     fingerprint: String,
     http_ports: &[u16; 7],  // 非TLS 模式下，http的端口
     https_ports: &[u16; 6], // TLS 模式下，https的端口
+    picker: &mut NodePicker,
 ) -> (String, JsonValue) {
-    for _ in 0..100 {
-        match selecting_config_of_node(toml_proxies, uri_proxy_type.clone(), uri_userid) {
-            Ok(prxy) => {
-                let node_type = prxy.node_type.as_str();
-                let toml_tag: String = prxy.node.remarks_prefix;
-                let host: String = prxy.node.host;
-                let server_name: String = prxy.node.server_name.unwrap_or_default();
-                let toml_ss_tls = prxy.node.tls.unwrap_or(true);
-                let path: String = prxy.node.path;
-
-                let condition = if ["vless", "trojan", "vmess"].contains(&node_type) {
-                    host.ends_with("workers.dev")
-                } else {
-                    !toml_ss_tls // ss协议的
-                };
+    let Some(resolved) = resolve_node(
+        nodes,
+        &csv_tag,
+        &csv_addr,
+        csv_port,
+        uri_port,
+        http_ports,
+        https_ports,
+        picker,
+    ) else {
+        return ("".to_string(), JsonValue::Null);
+    };
 
-                let (mut ports, reverse_ports) = match condition {
-                    true => (http_ports.to_vec(), https_ports.to_vec()),
-                    false => (https_ports.to_vec(), http_ports.to_vec()),
-                };
-                // 注意：不会检查配置文件的端口是否合法
-                ports = prxy.node.random_ports.unwrap_or(ports);
+    let node = resolved.node;
+    let host: String = node.host;
+    let server_name: String = node.server_name.unwrap_or_default();
+    let path: String = node.path;
 
-                let (port, is_continue) = match (uri_port == 0, csv_port == 0) {
-                    (true, true) => (*ports.choose(&mut rand::thread_rng()).unwrap(), false), // uri端口与csv端口都没有，就随机选一个端口
-                    (true, false) => (csv_port, reverse_ports.contains(&csv_port)), // csv端口有，就使用csv端口
-                    (false, true) => (uri_port, reverse_ports.contains(&uri_port)), // uri端口有，就使用uri端口
-                    (false, false) => (uri_port, false), // uri端口与csv端口都有，不管端口是否能使用，都使用uri端口
-                };
-                // 端口不匹配，开启tls和没有开启tls的端口不同，需要换另一个配置
-                if is_continue {
-                    continue;
-                }
-                // 节点的别名
-                let remarks = match (csv_tag.trim().is_empty(), toml_tag.is_empty()) {
-                    (true, true) => format!("{}:{}", csv_addr, port), // cvs_tag与toml_tag都没有
-                    (false, true) => format!("{}|{}:{}", csv_tag, csv_addr, port), // 仅有csv_tag
-                    (true, false) => format!("{}|{}:{}", toml_tag, csv_addr, port), // 仅有toml_tag
-                    (false, false) => format!("{}{}|{}:{}", toml_tag, csv_tag, csv_addr, port), // 既有csv_tag，也有toml_tag
-                };
-
-                match node_type {
-                    "vless" => {
-                        let (remarks, jsonvalue) = build_vless_singbox(
-                            remarks,
-                            csv_addr.clone(),
-                            port,
-                            prxy.node.uuid.unwrap_or_default(),
-                            host,
-                            server_name,
-                            path,
-                            fingerprint,
-                        );
-                        return (remarks, jsonvalue);
-                    }
-                    "vmess" => {
-                        let (remarks, jsonvalue) = build_vmess_singbox(
-                            remarks,
-                            csv_addr.clone(),
-                            port,
-                            prxy.node.uuid.unwrap_or_default(),
-                            host,
-                            server_name,
-                            path,
-                            fingerprint,
-                        );
-                        return (remarks, jsonvalue);
-                    }
-                    "trojan" => {
-                        let (remarks, jsonvalue) = build_trojan_singbox(
-                            remarks,
-                            csv_addr.clone(),
-                            port,
-                            prxy.node.password.unwrap_or_default(),
-                            host,
-                            server_name,
-                            path,
-                            fingerprint,
-                        );
-                        return (remarks, jsonvalue);
-                    }
-                    "ss" => {
-                        let (remarks, jsonvalue) = build_ss_singbox(
-                            remarks,
-                            csv_addr.clone(),
-                            port,
-                            prxy.node.password.unwrap_or("none".to_string()),
-                            toml_ss_tls,
-                            host,
-                            path,
-                        );
-                        return (remarks, jsonvalue);
-                    }
-                    _ => {
-                        return ("".to_string(), JsonValue::Null);
-                    }
-                }
-            }
-            Err(err) => eprintln!("警告：{}", err),
-        }
+    match resolved.node_type.as_str() {
+        "vless" => build_vless_singbox(
+            resolved.remarks,
+            resolved.server,
+            resolved.port,
+            node.uuid.unwrap_or_default(),
+            resolved.tls,
+            host,
+            server_name,
+            path,
+            fingerprint,
+        ),
+        "vmess" => build_vmess_singbox(
+            resolved.remarks,
+            resolved.server,
+            resolved.port,
+            node.uuid.unwrap_or_default(),
+            resolved.tls,
+            host,
+            server_name,
+            path,
+            fingerprint,
+        ),
+        "trojan" => build_trojan_singbox(
+            resolved.remarks,
+            resolved.server,
+            resolved.port,
+            node.password.unwrap_or_default(),
+            resolved.tls,
+            host,
+            server_name,
+            path,
+            fingerprint,
+        ),
+        "ss" => build_ss_singbox(
+            resolved.remarks,
+            resolved.server,
+            resolved.port,
+            node.password.unwrap_or("none".to_string()),
+            resolved.tls,
+            host,
+            path,
+        ),
+        _ => ("".to_string(), JsonValue::Null),
     }
-    return ("".to_string(), JsonValue::Null);
 }
 
 fn build_ss_singbox(
@@ -146,23 +109,21 @@ fn build_ss_singbox(
         "plugin_opts": plugin_value
     });
 
-    return (remarks, ss_with_jsonvalue);
+    (remarks, ss_with_jsonvalue)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn build_trojan_singbox(
     remarks: String,
     csv_addr: String,
     port: u16,
     toml_password: String,
+    tls: bool,
     toml_host: String,
     toml_server_name: String, // sni
     toml_path: String,
     fingerprint: String,
 ) -> (String, JsonValue) {
-    let tls = match !toml_host.ends_with("workers.dev") {
-        true => true,
-        false => false,
-    };
     let trojan_with_jsonvalue = json!({
         "type": "trojan",
         "tag": remarks,
@@ -189,20 +150,18 @@ fn build_trojan_singbox(
     (remarks, trojan_with_jsonvalue)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn build_vless_singbox(
     remarks: String,
     csv_addr: String,
     port: u16,
     toml_uuid: String,
+    tls: bool,
     toml_host: String,
     toml_server_name: String, // sni
     toml_path: String,
     fingerprint: String,
 ) -> (String, JsonValue) {
-    let tls = match !toml_host.ends_with("workers.dev") {
-        true => true,
-        false => false,
-    };
     let vless_with_jsonvalue = json!({
         "type": "vless",
         "tag": remarks,
@@ -229,20 +188,18 @@ fn build_vless_singbox(
     (remarks, vless_with_jsonvalue)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn build_vmess_singbox(
     remarks: String,
     csv_addr: String,
     port: u16,
     toml_uuid: String,
+    tls: bool,
     toml_host: String,
     toml_server_name: String, // sni
     toml_path: String,
     fingerprint: String,
 ) -> (String, JsonValue) {
-    let tls = match !toml_host.ends_with("workers.dev") {
-        true => true,
-        false => false,
-    };
     let vmess_with_jsonvalue = json!({
         "type": "vmess",
         "tag": remarks,
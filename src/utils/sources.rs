@@ -0,0 +1,332 @@
+use crate::utils::{file_data::MyData, toml::Sources};
+use reqwest::Url;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+/// 一个数据源：本地文件/文件夹，或者http(s)://链接，可以带上权重和最多读取的数量
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataSource {
+    pub location: String,     // 文件路径、文件夹路径或链接
+    pub weight: usize,        // 合并时，每轮从这个数据源取几个数据
+    pub limit: Option<usize>, // 最多从这个数据源读取几个数据
+}
+
+impl DataSource {
+    /// 解析一个数据源，如："./data/result.csv"、"https://x.com/ips.txt;limit=20;weight=2"
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut parts = value.split(';');
+        // 针对win11中"复制文件地址"出现双引号的情况
+        let location = parts.next().unwrap_or_default().trim().trim_matches('"');
+        if location.is_empty() {
+            return Err(format!("`{}` 中没有数据源", value));
+        }
+        let mut source = DataSource {
+            location: location.to_string(),
+            weight: 1,
+            limit: None,
+        };
+        for option in parts.map(str::trim).filter(|option| !option.is_empty()) {
+            let (key, number) = option
+                .split_once('=')
+                .ok_or(format!("`{}` 的格式不对，应该是key=数字", option))?;
+            let number: usize = number
+                .trim()
+                .parse()
+                .ok()
+                .filter(|number| *number > 0)
+                .ok_or(format!("`{}` 的值只能是大于0的整数", option))?;
+            match key.trim().to_lowercase().as_str() {
+                "weight" | "w" => source.weight = number,
+                "limit" | "n" => source.limit = Some(number),
+                _ => return Err(format!("`{}` 不支持，可选：weight、limit", option)),
+            }
+        }
+        Ok(source)
+    }
+
+    /// 是否为网络数据源
+    pub fn is_remote(&self) -> bool {
+        let location = self.location.to_lowercase();
+        location.starts_with("http://") || location.starts_with("https://")
+    }
+}
+
+/// 解析所有的source参数，每个参数中可以用|分隔多个数据源，数据源可以是config.toml中配置的简称
+pub fn parse_sources(
+    values: &[String],
+    names: &HashMap<String, String>,
+) -> Result<Vec<DataSource>, String> {
+    values
+        .iter()
+        .flat_map(|value| value.split('|'))
+        .filter(|value| !value.trim().is_empty())
+        .flat_map(|value| expand_name(value, names))
+        .map(|value| DataSource::parse(&value))
+        .collect()
+}
+
+// 将简称展开为配置的数据源，简称后面的;weight=N、;limit=N会应用到每个展开的数据源上
+fn expand_name(value: &str, names: &HashMap<String, String>) -> Vec<String> {
+    let (name, options) = match value.split_once(';') {
+        Some((name, options)) => (name, format!(";{}", options)),
+        None => (value, "".to_string()),
+    };
+    match names.get(name.trim()) {
+        Some(configured) => configured
+            .split('|')
+            .filter(|source| !source.trim().is_empty())
+            .map(|source| format!("{}{}", source, options))
+            .collect(),
+        None => vec![value.to_string()],
+    }
+}
+
+/// 检查数据源是否在config.toml允许的范围内，本地路径会转换为真实路径(解析..和符号链接)
+pub fn check_source(source: DataSource, config: &Sources) -> Result<DataSource, String> {
+    if source.is_remote() {
+        return match is_allowed_url(&source.location, config) {
+            true => Ok(source),
+            false => Err(format!("不允许获取 `{}`", source.location)),
+        };
+    }
+
+    // 不存在的路径也返回同样的错误，避免泄露允许范围以外的文件是否存在
+    let denied = || format!("`{}` 不存在或不在允许读取的文件夹中", source.location);
+    let path = Path::new(&source.location)
+        .canonicalize()
+        .map_err(|_| denied())?;
+    let allowed = config
+        .local_roots
+        .iter()
+        .filter_map(|root| Path::new(root.trim_matches('"')).canonicalize().ok())
+        .any(|root| path.starts_with(root));
+    match allowed {
+        true => Ok(DataSource {
+            location: path.to_string_lossy().into_owned(),
+            ..source
+        }),
+        false => Err(denied()),
+    }
+}
+
+/// 链接是否在允许的前缀或域名中
+pub fn is_allowed_url(location: &str, config: &Sources) -> bool {
+    let Ok(url) = Url::parse(location) else {
+        return false;
+    };
+    let normalized = url.as_str();
+    let prefix_allowed = config.remote_prefixes.iter().any(|prefix| {
+        let prefix = Url::parse(prefix).map_or(prefix.to_string(), |p| p.to_string());
+        // 前缀要在路径的分隔处结束，避免https://a.com匹配到https://a.com.evil.com
+        normalized.starts_with(&prefix)
+            && (prefix.ends_with('/')
+                || normalized[prefix.len()..].is_empty()
+                || normalized[prefix.len()..].starts_with(['/', '?', '#']))
+    });
+    let host_allowed = url.host_str().is_some_and(|host| {
+        config
+            .remote_hosts
+            .iter()
+            .any(|allowed| allowed.trim().eq_ignore_ascii_case(host))
+    });
+    prefix_allowed || host_allowed
+}
+
+/// 按权重轮流从各个数据源中取数据，合并成一个列表，相同的地址和端口只保留第一个
+pub fn merge_sources(source_datas: Vec<(usize, Vec<MyData>)>) -> Vec<MyData> {
+    let mut iters: Vec<(usize, std::vec::IntoIter<MyData>)> = source_datas
+        .into_iter()
+        .map(|(weight, datas)| (weight, datas.into_iter()))
+        .collect();
+    let mut seen_addr: HashSet<String> = HashSet::new();
+    let mut results: Vec<MyData> = Vec::new();
+
+    loop {
+        let mut taken = false;
+        for (weight, iter) in iters.iter_mut() {
+            for item in iter.by_ref().take(*weight) {
+                taken = true;
+                let addr_port = format!("{}:{}", item.addr, item.port.unwrap_or(0));
+                if seen_addr.insert(addr_port) {
+                    results.push(item);
+                }
+            }
+        }
+        if !taken {
+            break;
+        }
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::PathBuf};
+
+    // 每个测试使用单独的临时文件夹：root是允许读取的文件夹，outside在允许的范围以外
+    fn temp_dirs(name: &str) -> (PathBuf, PathBuf) {
+        let base = std::env::temp_dir().join(format!("sources-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let (root, outside) = (base.join("root"), base.join("outside"));
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(root.join("ips.txt"), "1.1.1.1\n").unwrap();
+        fs::write(outside.join("secret.txt"), "2.2.2.2\n").unwrap();
+        (root, outside)
+    }
+
+    fn sources_config(root: &Path) -> Sources {
+        Sources {
+            local_roots: vec![root.to_string_lossy().into_owned()],
+            remote_prefixes: vec!["https://a.com".to_string()],
+            remote_hosts: vec!["raw.githubusercontent.com".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn local(location: PathBuf) -> DataSource {
+        DataSource::parse(&location.to_string_lossy()).unwrap()
+    }
+
+    #[test]
+    fn allows_file_inside_root() {
+        let (root, _) = temp_dirs("inside");
+        let checked = check_source(local(root.join("ips.txt")), &sources_config(&root)).unwrap();
+        assert_eq!(
+            Path::new(&checked.location),
+            root.join("ips.txt").canonicalize().unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_parent_dir_traversal() {
+        let (root, _) = temp_dirs("traversal");
+        let source = local(root.join("..").join("outside").join("secret.txt"));
+        assert!(check_source(source, &sources_config(&root)).is_err());
+        // 不存在的文件跟范围以外的文件返回同样的错误
+        let missing = local(root.join("..").join("outside").join("missing.txt"));
+        let secret = local(root.join("..").join("outside").join("secret.txt"));
+        assert_eq!(
+            check_source(missing, &sources_config(&root)).unwrap_err(),
+            check_source(secret, &sources_config(&root))
+                .unwrap_err()
+                .replace("secret.txt", "missing.txt")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlink_escaping_root() {
+        let (root, outside) = temp_dirs("symlink");
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("linked_dir")).unwrap();
+        let config = sources_config(&root);
+        assert!(check_source(local(root.join("link.txt")), &config).is_err());
+        assert!(check_source(local(root.join("linked_dir").join("secret.txt")), &config).is_err());
+    }
+
+    #[test]
+    fn url_prefix_must_end_at_path_boundary() {
+        let config = sources_config(Path::new("./data"));
+        assert!(is_allowed_url("https://a.com", &config));
+        assert!(is_allowed_url("https://a.com/ips.txt", &config));
+        assert!(is_allowed_url("https://a.com?list=1", &config));
+        assert!(!is_allowed_url("https://a.com.evil.com/ips.txt", &config));
+        assert!(!is_allowed_url("https://a.comevil.com/ips.txt", &config));
+        assert!(!is_allowed_url("https://a.com@evil.com/ips.txt", &config));
+        assert!(!is_allowed_url("http://a.com/ips.txt", &config));
+    }
+
+    #[test]
+    fn url_host_must_match_exactly() {
+        let config = sources_config(Path::new("./data"));
+        assert!(is_allowed_url(
+            "https://RAW.githubusercontent.com/x/ips.txt",
+            &config
+        ));
+        assert!(!is_allowed_url(
+            "https://raw.githubusercontent.com.evil.com/ips.txt",
+            &config
+        ));
+        assert!(!is_allowed_url("not a url", &config));
+    }
+
+    #[test]
+    fn expands_named_sources_with_options() {
+        let names = HashMap::from([(
+            "best".to_string(),
+            "./data/result.csv|https://a.com/ips.txt".to_string(),
+        )]);
+        let values = vec!["best;weight=2".to_string(), "./data/ips.txt".to_string()];
+        let sources = parse_sources(&values, &names).unwrap();
+        let locations: Vec<(&str, usize)> = sources
+            .iter()
+            .map(|source| (source.location.as_str(), source.weight))
+            .collect();
+        assert_eq!(
+            locations,
+            vec![
+                ("./data/result.csv", 2),
+                ("https://a.com/ips.txt", 2),
+                ("./data/ips.txt", 1)
+            ]
+        );
+        // 没有配置的简称当作路径
+        let unknown = parse_sources(&["other".to_string()], &names).unwrap();
+        assert_eq!(unknown[0].location, "other");
+    }
+
+    fn data(addr: &str, port: u16) -> MyData {
+        MyData {
+            addr: addr.to_string(),
+            port: Some(port),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_weight_and_limit() {
+        assert_eq!(
+            DataSource::parse("\"./data/result.csv\"; weight=3;LIMIT=20").unwrap(),
+            DataSource {
+                location: "./data/result.csv".to_string(),
+                weight: 3,
+                limit: Some(20),
+            }
+        );
+        assert!(DataSource::parse(";weight=2").is_err());
+        assert!(DataSource::parse("a.txt;weight=0").is_err());
+        assert!(DataSource::parse("a.txt;weight").is_err());
+        assert!(DataSource::parse("a.txt;size=2").is_err());
+        assert!(DataSource::parse("HTTPS://a.com/ips.txt")
+            .unwrap()
+            .is_remote());
+    }
+
+    #[test]
+    fn merges_by_weight_in_turns() {
+        let merged = merge_sources(vec![
+            (2, vec![data("a1", 443), data("a2", 443), data("a3", 443)]),
+            (1, vec![data("b1", 443), data("b2", 443)]),
+        ]);
+        let addrs: Vec<&str> = merged.iter().map(|item| item.addr.as_str()).collect();
+        assert_eq!(addrs, ["a1", "a2", "b1", "a3", "b2"]);
+    }
+
+    #[test]
+    fn merge_keeps_first_of_same_addr_and_port() {
+        let merged = merge_sources(vec![
+            (1, vec![data("1.1.1.1", 443), data("1.1.1.1", 443)]),
+            (1, vec![data("1.1.1.1", 443), data("1.1.1.1", 8443)]),
+        ]);
+        let addrs: Vec<String> = merged
+            .iter()
+            .map(|item| format!("{}:{}", item.addr, item.port.unwrap()))
+            .collect();
+        assert_eq!(addrs, ["1.1.1.1:443", "1.1.1.1:8443"]);
+    }
+}
@@ -0,0 +1,323 @@
+use crate::utils::{hash::stable_hash, toml::SelectedNode};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashSet;
+
+/// IP/域名分配节点配置的方式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Strategy {
+    #[default]
+    Random, // 随机挑选节点
+    RoundRobin, // 按顺序轮流使用节点
+    Hash,       // 根据地址的哈希值挑选节点，同一个地址总是使用同一个节点
+    Cross,      // 每个地址跟每个节点都组合一次
+}
+
+impl Strategy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "random" => Ok(Strategy::Random),
+            "round_robin" | "roundrobin" | "rr" => Ok(Strategy::RoundRobin),
+            "hash" => Ok(Strategy::Hash),
+            "cross" => Ok(Strategy::Cross),
+            _ => Err(format!(
+                "strategy的值只能是random、round_robin、hash、cross，不能是：{}",
+                value
+            )),
+        }
+    }
+}
+
+/// 没有seed参数时的随机数种子：token和查询字符串的哈希值，相同的请求生成的订阅完全一样
+pub fn default_seed(token: Option<&str>, query_str: &str) -> u64 {
+    stable_hash(&[token.unwrap_or_default(), query_str])
+}
+
+/// 按分配方式挑选节点，同时提供挑选端口、指纹使用的随机数生成器
+pub struct NodePicker {
+    pub rng: ChaCha8Rng, // 算法固定，同一个种子在不同版本中生成的随机数一样
+    strategy: Strategy,
+    counter: usize,         // round_robin：下一次使用第几个节点
+    round: usize,           // cross：当前地址跟第几个节点组合
+    names: HashSet<String>, // 已经使用的节点别名
+}
+
+impl NodePicker {
+    pub fn new(strategy: Strategy, seed: u64) -> Self {
+        NodePicker {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            strategy,
+            counter: 0,
+            round: 0,
+            names: HashSet::new(),
+        }
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    /// 开始为一个地址挑选节点，round是cross方式中跟第几个节点组合
+    pub fn start(&mut self, round: usize) {
+        self.round = round;
+    }
+
+    /// 别名跟之前的节点重复时，在后面加上序号（" 2"、" 3"……），客户端要求节点的名称不能重复
+    pub fn unique_name(&mut self, name: String) -> String {
+        let mut unique = name.clone();
+        let mut index = 2;
+        while self.names.contains(&unique) {
+            unique = format!("{} {}", name, index);
+            index += 1;
+        }
+        self.names.insert(unique.clone());
+        unique
+    }
+
+    /// 从候选节点(已经剔除了不能使用的节点)中挑选一个，返回序号，candidates不能为空
+    pub fn pick(&mut self, candidates: &[&SelectedNode], addr: &str) -> usize {
+        let len = candidates.len();
+        match self.strategy {
+            Strategy::Random => self.rng.gen_range(0..len),
+            Strategy::RoundRobin => {
+                let index = self.counter % len;
+                self.counter += 1;
+                index
+            }
+            // 哈希值最大的节点
+            Strategy::Hash => (0..len)
+                .max_by_key(|&i| rendezvous_hash(addr, candidates[i]))
+                .unwrap_or(0),
+            // round不会超过候选节点数(每个地址的组合数就是候选节点数)
+            Strategy::Cross => self.round % len,
+        }
+    }
+}
+
+// 地址跟节点的哈希值(最高随机权重哈希)，增减节点时，只有少部分地址会换节点；使用固定的哈希算法，升级Rust后分配结果不变
+fn rendezvous_hash(addr: &str, candidate: &SelectedNode) -> u64 {
+    let node = &candidate.node;
+    stable_hash(&[
+        addr,
+        &candidate.node_type,
+        &node.host,
+        &node.path,
+        node.uuid.as_deref().unwrap_or_default(),
+        node.password.as_deref().unwrap_or_default(),
+        &node.remarks_prefix,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{
+        toml::{resolve_node, usable_nodes, Proxy},
+        v2ray::build_v2ray_link,
+    };
+
+    const HTTP_PORTS: [u16; 7] = [80, 8080, 8880, 2052, 2082, 2086, 2095];
+    const HTTPS_PORTS: [u16; 6] = [443, 2053, 2083, 2087, 2096, 8443];
+
+    // count个开启tls的vless节点，remarks_prefix为空时，同一个地址和端口的别名会重复
+    fn vless_nodes(count: usize, remarks_prefix: bool) -> Vec<SelectedNode> {
+        let toml: String = (0..count)
+            .map(|i| {
+                let prefix = match remarks_prefix {
+                    true => format!("n{}", i),
+                    false => "".to_string(),
+                };
+                format!(
+                    "[[vless]]\nremarks_prefix = \"{}\"\nuuid = \"0195bf7c-f8c6-7add-aa36-a2145cc38e{:02x}\"\nhost = \"v{}.example.com\"\nserver_name = \"v{}.example.com\"\npath = \"/\"\ntls = true\n",
+                    prefix, i, i, i
+                )
+            })
+            .collect();
+        let proxies: Proxy = toml::from_str(&toml).unwrap();
+        usable_nodes(&proxies, "", 0, |_| true)
+    }
+
+    fn addrs(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| format!("104.16.{}.{}", i / 256, i % 256))
+            .collect()
+    }
+
+    // 跟/sub一样：每个地址挑选一次指纹，再构建v2ray链接
+    fn subscription(strategy: Strategy, seed: u64, nodes: &[SelectedNode]) -> Vec<String> {
+        let mut picker = NodePicker::new(strategy, seed);
+        addrs(20)
+            .into_iter()
+            .map(|addr| {
+                picker.start(0);
+                let fingerprint = ["chrome", "firefox", "safari"][picker.rng.gen_range(0..3)];
+                build_v2ray_link(
+                    nodes,
+                    "".to_string(),
+                    addr,
+                    0,
+                    0,
+                    fingerprint.to_string(),
+                    &HTTP_PORTS,
+                    &HTTPS_PORTS,
+                    &mut picker,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_builds_identical_subscription() {
+        let nodes = vless_nodes(3, true);
+        for strategy in [
+            Strategy::Random,
+            Strategy::RoundRobin,
+            Strategy::Hash,
+            Strategy::Cross,
+        ] {
+            assert_eq!(
+                subscription(strategy, 42, &nodes),
+                subscription(strategy, 42, &nodes)
+            );
+        }
+    }
+
+    #[test]
+    fn different_seed_changes_random_output() {
+        let nodes = vless_nodes(3, true);
+        assert_ne!(
+            subscription(Strategy::Random, 1, &nodes),
+            subscription(Strategy::Random, 2, &nodes)
+        );
+    }
+
+    #[test]
+    fn default_seed_is_stable() {
+        let seed = default_seed(Some("token"), "target=clash&n=20");
+        assert_eq!(seed, default_seed(Some("token"), "target=clash&n=20"));
+        // 固定的哈希算法，升级Rust后也不变
+        assert_eq!(seed, stable_hash(&["token", "target=clash&n=20"]));
+        assert_eq!(default_seed(None, "n=20"), default_seed(Some(""), "n=20"));
+        assert_ne!(seed, default_seed(Some("other"), "target=clash&n=20"));
+        assert_ne!(seed, default_seed(Some("token"), "target=clash&n=21"));
+    }
+
+    #[test]
+    fn unique_name_appends_index() {
+        let mut picker = NodePicker::new(Strategy::Cross, 0);
+        assert_eq!(picker.unique_name("a:443".to_string()), "a:443");
+        assert_eq!(picker.unique_name("a:443".to_string()), "a:443 2");
+        assert_eq!(picker.unique_name("a:443".to_string()), "a:443 3");
+        // 加上序号后跟已有的别名重复，继续往后加
+        assert_eq!(picker.unique_name("b 2".to_string()), "b 2");
+        assert_eq!(picker.unique_name("b".to_string()), "b");
+        assert_eq!(picker.unique_name("b".to_string()), "b 3");
+    }
+
+    // 每个地址挑选到的节点(host)
+    fn assign(picker: &mut NodePicker, nodes: &[SelectedNode], addrs: &[String]) -> Vec<String> {
+        let candidates: Vec<&SelectedNode> = nodes.iter().collect();
+        addrs
+            .iter()
+            .map(|addr| {
+                picker.start(0);
+                candidates[picker.pick(&candidates, addr)].node.host.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_cycles_over_fitting_nodes() {
+        let mut nodes = vless_nodes(3, true);
+        nodes[1].node.tls = Some(false);
+        let mut picker = NodePicker::new(Strategy::RoundRobin, 0);
+        // 443是tls端口，跳过没有开启tls的第2个节点
+        let hosts: Vec<String> = addrs(4)
+            .iter()
+            .map(|addr| {
+                picker.start(0);
+                let resolved = resolve_node(
+                    &nodes,
+                    "",
+                    addr,
+                    443,
+                    0,
+                    &HTTP_PORTS,
+                    &HTTPS_PORTS,
+                    &mut picker,
+                )
+                .unwrap();
+                resolved.node.host
+            })
+            .collect();
+        assert_eq!(
+            hosts,
+            [
+                "v0.example.com",
+                "v2.example.com",
+                "v0.example.com",
+                "v2.example.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn hash_moves_few_addresses_when_nodes_change() {
+        let addrs = addrs(1000);
+        let nodes = vless_nodes(5, true);
+        let mut picker = NodePicker::new(Strategy::Hash, 0);
+        let before = assign(&mut picker, &nodes[..4], &addrs);
+        // 跟种子无关
+        let mut other_seed = NodePicker::new(Strategy::Hash, 1);
+        assert_eq!(before, assign(&mut other_seed, &nodes[..4], &addrs));
+
+        // 增加一个节点：只有分到新节点的地址会变，大约1/5
+        let added = assign(&mut picker, &nodes, &addrs);
+        let moved: Vec<usize> = (0..addrs.len())
+            .filter(|&i| before[i] != added[i])
+            .collect();
+        assert!(moved.iter().all(|&i| added[i] == "v4.example.com"));
+        assert!((100..=300).contains(&moved.len()), "moved {}", moved.len());
+
+        // 删除一个节点：只有原来分到这个节点的地址会变
+        let removed = assign(&mut picker, &nodes[1..4], &addrs);
+        for i in 0..addrs.len() {
+            if before[i] != "v0.example.com" {
+                assert_eq!(before[i], removed[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn cross_combines_every_address_and_node() {
+        // 没有remarks_prefix，同一个地址和端口的别名都一样
+        let nodes = vless_nodes(3, false);
+        let mut picker = NodePicker::new(Strategy::Cross, 0);
+        let mut combos = HashSet::new();
+        let mut names = Vec::new();
+        for addr in addrs(4) {
+            for round in 0..nodes.len() {
+                picker.start(round);
+                let resolved = resolve_node(
+                    &nodes,
+                    "",
+                    &addr,
+                    443,
+                    0,
+                    &HTTP_PORTS,
+                    &HTTPS_PORTS,
+                    &mut picker,
+                )
+                .unwrap();
+                combos.insert((addr.clone(), resolved.node.host));
+                names.push(resolved.remarks);
+            }
+        }
+        assert_eq!(combos.len(), 4 * 3);
+        assert_eq!(
+            &names[..3],
+            ["104.16.0.0:443", "104.16.0.0:443 2", "104.16.0.0:443 3"]
+        );
+        assert_eq!(names.iter().collect::<HashSet<_>>().len(), names.len());
+    }
+}
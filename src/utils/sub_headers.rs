@@ -0,0 +1,75 @@
+use crate::utils::toml::Subscription;
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+const DEFAULT_TITLE: &str = "cfwks-subconverter";
+
+// 不同目标客户端的配置文件扩展名
+fn file_extension(target: &str) -> &'static str {
+    match target {
+        "clash" => "yaml",
+        "singbox" | "xray" => "json",
+        "surge" | "surfboard" | "quanx" | "loon" => "conf",
+        _ => "txt",
+    }
+}
+
+/// 根据[subscription]的配置，生成订阅的响应头
+pub fn build_subscription_headers(
+    subscription: &Subscription,
+    target: &str,
+) -> Vec<(String, String)> {
+    let title = subscription.title.as_deref().unwrap_or(DEFAULT_TITLE);
+    let mut headers: Vec<(String, String)> = Vec::new();
+
+    // 文件名可能含有中文，使用RFC 5987的形式
+    headers.push((
+        "Content-Disposition".to_string(),
+        format!(
+            "attachment; filename*=UTF-8''{}.{}",
+            urlencoding::encode(title),
+            file_extension(target)
+        ),
+    ));
+    headers.push((
+        "profile-title".to_string(),
+        format!("base64:{}", STANDARD.encode(title)),
+    ));
+
+    if let Some(hours) = subscription.update_interval {
+        headers.push(("profile-update-interval".to_string(), hours.to_string()));
+    }
+    if let Some(url) = &subscription.web_page_url {
+        headers.push(("profile-web-page-url".to_string(), url.to_string()));
+    }
+
+    // 流量和到期时间，都没有配置就不返回该响应头
+    let has_userinfo = subscription.upload.is_some()
+        || subscription.download.is_some()
+        || subscription.total.is_some()
+        || subscription.expire.is_some();
+    if has_userinfo {
+        let mut userinfo = format!(
+            "upload={}; download={}; total={}",
+            subscription.upload.unwrap_or(0),
+            subscription.download.unwrap_or(0),
+            subscription.total.unwrap_or(0)
+        );
+        if let Some(expire) = subscription.expire {
+            userinfo.push_str(&format!("; expire={}", expire));
+        }
+        headers.push(("subscription-userinfo".to_string(), userinfo));
+    }
+
+    headers
+}
+
+/// surge托管配置的首行，客户端根据它自动更新配置
+pub fn surge_managed_config(subscription: &Subscription, url: &str) -> Option<String> {
+    subscription.update_interval.map(|hours| {
+        format!(
+            "#!MANAGED-CONFIG {} interval={} strict=false",
+            url,
+            hours as u64 * 3600
+        )
+    })
+}
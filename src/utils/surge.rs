@@ -0,0 +1,110 @@
+use crate::utils::{
+    conf_template::add_conf_template,
+    strategy::NodePicker,
+    toml::{resolve_node, SelectedNode},
+};
+
+// Surge/Surfboard 不支持 vless 和 ss+v2ray-plugin，trojan 只支持 tls 模式
+pub fn is_supported(selected: &SelectedNode) -> bool {
+    match selected.node_type.as_str() {
+        "vmess" => true,
+        "trojan" => selected.is_tls(),
+        _ => false,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn build_surge_line(
+    nodes: &[SelectedNode], // usable_nodes返回的节点（已经剔除了客户端不支持的节点）
+    csv_tag: String,
+    csv_addr: String,
+    csv_port: u16,
+    uri_port: u16,
+    http_ports: &[u16; 7],  // 非TLS 模式下，http的端口
+    https_ports: &[u16; 6], // TLS 模式下，https的端口
+    picker: &mut NodePicker,
+) -> (String, String) {
+    let Some(resolved) = resolve_node(
+        nodes,
+        &csv_tag,
+        &csv_addr,
+        csv_port,
+        uri_port,
+        http_ports,
+        https_ports,
+        picker,
+    ) else {
+        return ("".to_string(), "".to_string());
+    };
+
+    // Surge的节点名称中，不能含有逗号和等号
+    let remarks = resolved.remarks.replace([',', '='], " ");
+    let node = resolved.node;
+    // 没有设置server_name时，sni使用host
+    let server_name = node.server_name.clone().unwrap_or(node.host.clone());
+
+    let line = match resolved.node_type.as_str() {
+        "vmess" => build_vmess_surge(
+            &remarks,
+            &resolved.server,
+            resolved.port,
+            &node.uuid.unwrap_or_default(),
+            resolved.tls,
+            &node.host,
+            &server_name,
+            &node.path,
+        ),
+        "trojan" => build_trojan_surge(
+            &remarks,
+            &resolved.server,
+            resolved.port,
+            &node.password.unwrap_or_default(),
+            &node.host,
+            &server_name,
+            &node.path,
+        ),
+        _ => return ("".to_string(), "".to_string()),
+    };
+    (remarks, line)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_vmess_surge(
+    remarks: &str,
+    server: &str,
+    port: u16,
+    uuid: &str,
+    tls: bool,
+    host: &str,
+    sni: &str,
+    path: &str,
+) -> String {
+    let mut line = format!(
+        "{} = vmess, {}, {}, username={}, ws=true, ws-path={}, ws-headers=Host:{}, vmess-aead=true",
+        remarks, server, port, uuid, path, host
+    );
+    if tls {
+        line.push_str(&format!(", tls=true, sni={}, skip-cert-verify=true", sni));
+    }
+    line
+}
+
+fn build_trojan_surge(
+    remarks: &str,
+    server: &str,
+    port: u16,
+    password: &str,
+    host: &str,
+    sni: &str,
+    path: &str,
+) -> String {
+    format!(
+        "{} = trojan, {}, {}, password={}, sni={}, skip-cert-verify=true, ws=true, ws-path={}, ws-headers=Host:{}",
+        remarks, server, port, password, sni, path, host
+    )
+}
+
+/// 将节点插入模板的[Proxy]中，并将[Proxy Group]中的{all}替换为节点名称
+pub fn add_surge_template(template: &str, proxies_vec: Vec<(String, String)>) -> String {
+    add_conf_template(template, "[Proxy]", "[Proxy Group]", proxies_vec)
+}
@@ -1,13 +1,117 @@
+use crate::utils::strategy::NodePicker;
 use rand::seq::SliceRandom;
 use serde::Deserialize;
-use std::usize;
+use std::collections::HashMap;
 
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Clone, Default)]
 pub struct Config {
+    #[serde(default)]
+    pub subscription: Subscription,
+    #[serde(default)]
+    pub sources: Sources,
+    #[serde(default)]
+    pub dns: Dns,
+    #[serde(default)]
+    pub users: Vec<User>,
+    #[serde(default)]
+    pub limits: Limits,
     pub proxies: Proxy,
 }
 
+// /sub的请求速度限制(令牌桶)，per_minute为0表示不限制
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Limits {
+    pub ip_per_minute: u32,    // 每个IP每分钟可以请求几次
+    pub ip_burst: u32,         // 每个IP最多可以连续请求几次
+    pub token_per_minute: u32, // 每个token每分钟可以请求几次
+    pub token_burst: u32,      // 每个token最多可以连续请求几次
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            ip_per_minute: 0, // 默认不限制，升级后没有[limits]的配置文件不会突然返回429
+            ip_burst: 10,
+            token_per_minute: 0,
+            token_burst: 10,
+        }
+    }
+}
+
+// 订阅的用户，配置了[[users]]后，需要使用token才能获取订阅
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct User {
+    pub name: Option<String>,
+    pub token: String,
+    #[serde(default)]
+    pub nodes: Vec<String>, // 可以使用的节点，如：vless、trojan:1、tag:①，为空时可以使用全部节点
+    #[serde(default)]
+    pub params: toml::Table, // 默认的URI参数，请求中有相同的参数时，使用请求中的参数
+    pub expire: Option<toml::Value>, // 到期时间，日期(如：2026-12-31，当天结束时到期)或Unix时间戳(秒)
+    pub max_requests_per_day: Option<u32>, // 每天(UTC)最多请求几次
+}
+
+// 数据源(source参数)的访问限制，以及数据源的简称
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Sources {
+    pub default: String,                // 没有source参数时，使用的数据源
+    pub local_roots: Vec<String>,       // 允许读取的本地文件夹(包括子文件夹)
+    pub remote_prefixes: Vec<String>,   // 允许获取的链接前缀
+    pub remote_hosts: Vec<String>,      // 允许获取的域名(不限制路径)
+    pub names: HashMap<String, String>, // 数据源的简称，如：best = "./data/result.csv"
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Sources {
+            default: "./data".to_string(),
+            local_roots: vec!["./data".to_string()],
+            remote_prefixes: Vec::new(),
+            remote_hosts: Vec::new(),
+            names: HashMap::new(),
+        }
+    }
+}
+
+// 解析数据源中的域名(resolve参数)使用的DNS服务器
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct Dns {
+    pub server: String, // system：系统的DNS，stub：只使用hosts，或者DNS服务器的地址，如：1.1.1.1、8.8.8.8:53
+    pub timeout: u64,   // 查询超时，单位：秒
+    pub hosts: HashMap<String, Vec<String>>, // 本地的解析结果，优先于DNS服务器
+}
+
+impl Default for Dns {
+    fn default() -> Self {
+        Dns {
+            server: "system".to_string(),
+            timeout: 3,
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+// 订阅信息，用于生成订阅的响应头
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Subscription {
+    pub title: Option<String>,
+    pub update_interval: Option<u32>, // 单位：小时
+    pub web_page_url: Option<String>,
+    pub upload: Option<u64>,   // 单位：字节
+    pub download: Option<u64>, // 单位：字节
+    pub total: Option<u64>,    // 单位：字节
+    pub expire: Option<u64>,   // Unix时间戳(秒)
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Clone, Default)]
 pub struct Proxy {
@@ -17,19 +121,67 @@ pub struct Proxy {
     vmess: Option<Vec<Node>>,
 }
 
+impl Proxy {
+    // 按类型返回toml中的节点配置（没有配置的类型为空切片）
+    pub fn node_groups(&self) -> [(&'static str, &[Node]); 4] {
+        [
+            ("vless", self.vless.as_deref().unwrap_or_default()),
+            ("trojan", self.trojan.as_deref().unwrap_or_default()),
+            ("ss", self.ss.as_deref().unwrap_or_default()),
+            ("vmess", self.vmess.as_deref().unwrap_or_default()),
+        ]
+    }
+
+    // 只保留符合条件的节点，参数是节点类型、在同类型中的序号(从1开始)、节点配置
+    pub fn retain_nodes(&self, keep: impl Fn(&str, usize, &Node) -> bool) -> Proxy {
+        let retain = |node_type: &str, nodes: &Option<Vec<Node>>| {
+            nodes
+                .as_ref()
+                .map(|nodes| {
+                    nodes
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, node)| keep(node_type, i + 1, node))
+                        .map(|(_, node)| node.clone())
+                        .collect::<Vec<Node>>()
+                })
+                // 空数组当作没有配置，避免随机选择时没有节点可选
+                .filter(|nodes| !nodes.is_empty())
+        };
+        Proxy {
+            vless: retain("vless", &self.vless),
+            trojan: retain("trojan", &self.trojan),
+            ss: retain("ss", &self.ss),
+            vmess: retain("vmess", &self.vmess),
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Clone, Default)]
 pub struct Node {
     pub remarks_prefix: String,
     pub uuid: Option<String>,     // vless/vmess拥有
     pub password: Option<String>, // trojan/ss拥有
-    pub tls: Option<bool>,        // ss拥有
+    pub tls: Option<bool>,        // 没有设置时，ss默认开启tls，其它的根据host是否为workers.dev判断
+    pub port_mode: Option<String>, // 端口的选择方式，可选：auto(csv、uri中的端口要跟tls一致)、any(不检查csv、uri中的端口)，默认auto
     pub host: String,
     pub server_name: Option<String>,
     pub path: String,
     pub random_ports: Option<Vec<u16>>,
 }
 
+impl Node {
+    // 节点是否开启tls，优先使用配置文件中的tls字段，没有设置才根据host的后缀推断
+    pub fn is_tls(&self, node_type: &str) -> bool {
+        match self.tls {
+            Some(tls) => tls,
+            None if node_type == "ss" => true,
+            None => !self.host.ends_with("workers.dev"),
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Clone, Default)]
 pub struct SelectedNode {
@@ -37,166 +189,47 @@ pub struct SelectedNode {
     pub node: Node,
 }
 
-// 筛选数据
-#[allow(dead_code)]
-enum FilterCondition {
-    Text(String), // 节点类型
-    Number(u8),   // 节点id，从1开始，超出范围就随机
+impl SelectedNode {
+    pub fn is_tls(&self) -> bool {
+        self.node.is_tls(&self.node_type)
+    }
 }
 
-// 选中某个节点的配置
-#[allow(dead_code)]
-pub fn selecting_config_of_node(
-    proxies: &Proxy,
-    poxy_type: String,
-    node_id: u8,
-) -> Result<SelectedNode, String> {
-    let mut rng = rand::thread_rng();
-
-    let vless_length: u8 = match proxies.vless.clone() {
-        Some(vless) => vless.clone().len().try_into().unwrap(),
-        None => 0,
-    };
-    let trojan_length: u8 = match proxies.trojan.clone() {
-        Some(trojan) => trojan.clone().len().try_into().unwrap(),
-        None => 0,
+// 可以挑选的节点：指定了节点类型就只使用这个类型的节点，否则使用全部节点
+pub fn candidate_nodes(proxies: &Proxy, poxy_type: &str) -> Vec<SelectedNode> {
+    let typed_nodes = match poxy_type {
+        "vless" => proxies.vless.as_ref(),
+        "trojan" => proxies.trojan.as_ref(),
+        "ss" => proxies.ss.as_ref(),
+        "vmess" => proxies.vmess.as_ref(),
+        _ => return extend_all_nodes(proxies),
     };
-    let ss_length: u8 = match proxies.ss.clone() {
-        Some(ss) => ss.clone().len().try_into().unwrap(),
-        None => 0,
-    };
-    let vmess_length: u8 = match proxies.vmess.clone() {
-        Some(vmess) => vmess.clone().len().try_into().unwrap(),
-        None => 0,
-    };
-    let all_length = vless_length + trojan_length + ss_length + vmess_length;
-
-    match (
-        FilterCondition::Text(poxy_type.clone()),
-        FilterCondition::Number(node_id),
-    ) {
-        // vless+指定id
-        (FilterCondition::Text(ref s), FilterCondition::Number(n))
-            if s == "vless" && (1..=vless_length).contains(&n) =>
-        {
-            match proxies.vless.clone() {
-                Some(vless) => Ok(SelectedNode {
-                    node_type: "vless".to_string(),
-                    node: vless[(n as usize) - 1].clone(),
-                }),
-                None => Err("发生未知错误！".to_string()),
-            }
-        }
-
-        // vless+随机id
-        (FilterCondition::Text(ref s), FilterCondition::Number(_)) if s == "vless" => {
-            match proxies.vless.clone() {
-                Some(vless) => Ok(SelectedNode {
-                    node_type: "vless".to_string(),
-                    node: vless.choose(&mut rng).unwrap().clone(),
-                }),
-                None => Err("发生未知错误！".to_string()),
-            }
-        }
-
-        // ——————————————————————————————————————————————————————————————————————————————————————
-
-        // trojan+指定id
-        (FilterCondition::Text(ref s), FilterCondition::Number(n))
-            if s == "trojan" && (1..=trojan_length).contains(&n) =>
-        {
-            match proxies.trojan.clone() {
-                Some(trojan) => Ok(SelectedNode {
-                    node_type: "trojan".to_string(),
-                    node: trojan[(n as usize) - 1].clone(),
-                }),
-                None => Err("发生未知错误！".to_string()),
-            }
-        }
-
-        // trojan+随机id
-        (FilterCondition::Text(ref s), FilterCondition::Number(_)) if s == "trojan" => {
-            match proxies.trojan.clone() {
-                Some(trojan) => Ok(SelectedNode {
-                    node_type: "trojan".to_string(),
-                    node: trojan.choose(&mut rng).unwrap().clone(),
-                }),
-                None => Err("发生未知错误！".to_string()),
-            }
-        }
-
-        // ——————————————————————————————————————————————————————————————————————————————————————
-
-        // ss+指定id
-        (FilterCondition::Text(ref s), FilterCondition::Number(n))
-            if s == "ss" && (1..=ss_length).contains(&n) =>
-        {
-            match proxies.ss.clone() {
-                Some(ss) => Ok(SelectedNode {
-                    node_type: "ss".to_string(),
-                    node: ss[(n as usize) - 1].clone(),
-                }),
-                None => Err("发生未知错误！".to_string()),
-            }
-        }
-
-        // ss+随机id
-        (FilterCondition::Text(ref s), FilterCondition::Number(_)) if s == "ss" => {
-            match proxies.ss.clone() {
-                Some(ss) => Ok(SelectedNode {
-                    node_type: "ss".to_string(),
-                    node: ss.choose(&mut rng).unwrap().clone(),
-                }),
-                None => Err("发生未知错误！".to_string()),
-            }
-        }
-        
-        // ——————————————————————————————————————————————————————————————————————————————————————
-
-        // vmess+指定id
-        (FilterCondition::Text(ref s), FilterCondition::Number(n))
-            if s == "vmess" && (1..=vmess_length).contains(&n) =>
-        {
-            match proxies.vmess.clone() {
-                Some(vmess) => Ok(SelectedNode {
-                    node_type: "vmess".to_string(),
-                    node: vmess[(n as usize) - 1].clone(),
-                }),
-                None => Err("发生未知错误！".to_string()),
-            }
-        }
-
-        // vmess+随机id
-        (FilterCondition::Text(ref s), FilterCondition::Number(_)) if s == "vmess" => {
-            match proxies.vmess.clone() {
-                Some(vmess) => Ok(SelectedNode {
-                    node_type: "vmess".to_string(),
-                    node: vmess.choose(&mut rng).unwrap().clone(),
-                }),
-                None => Err("发生未知错误！".to_string()),
-            }
-        }
-
-        // ——————————————————————————————————————————————————————————————————————————————————————
-
-        // 指定id，不论vless还是trojan、ss、vmess
-        (_, FilterCondition::Number(n)) if (1..=all_length).contains(&n) => {
-            let all_nodes = extend_all_nodes(proxies);
-            match all_nodes.is_empty() {
-                true => Err("发生未知错误！".to_string()),
-                false => Ok(all_nodes[(n as usize) - 1].clone()),
-            }
-        }
+    typed_nodes
+        .map(|nodes| {
+            nodes
+                .iter()
+                .map(|node| SelectedNode {
+                    node_type: poxy_type.to_string(),
+                    node: node.clone(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
 
-        // 随机节点，不论vless还是trojan、ss
-        (_, _) => {
-            let all_nodes = extend_all_nodes(proxies);
-            all_nodes
-                .choose(&mut rng)
-                .cloned()
-                .ok_or("发生未知错误！".to_string())
-        }
+// 可以使用的节点：node_id在范围内就只使用指定的节点(从1开始)，再剔除目标客户端不支持的节点
+pub fn usable_nodes(
+    proxies: &Proxy,
+    poxy_type: &str,
+    node_id: u8,
+    is_supported: fn(&SelectedNode) -> bool,
+) -> Vec<SelectedNode> {
+    let mut candidates = candidate_nodes(proxies, poxy_type);
+    if (1..=candidates.len()).contains(&(node_id as usize)) {
+        candidates = vec![candidates.swap_remove(node_id as usize - 1)];
     }
+    candidates.retain(is_supported);
+    candidates
 }
 
 // 合并toml中所有trojan和vless、ss节点的配置（先trojan后vless、ss）
@@ -241,11 +274,95 @@ fn extend_all_nodes(proxies: &Proxy) -> Vec<SelectedNode> {
             .collect(),
         None => Vec::new(),
     };
-    let all_nodes: Vec<SelectedNode> = trojan_vec
+    trojan_vec
         .into_iter()
         .chain(vless_vec)
         .chain(ss_vec)
         .chain(vmess_vec)
+        .collect()
+}
+
+// 确定了端口、TLS和别名的节点，各个客户端的构建函数共用
+#[derive(Debug, Clone)]
+pub struct ResolvedNode {
+    pub node_type: String,
+    pub remarks: String,
+    pub server: String,
+    pub port: u16,
+    pub tls: bool,
+    pub node: Node,
+}
+
+// 节点能不能使用这个地址的端口：csv、uri中的端口要跟节点的tls模式一致(port_mode为any时不检查)，没有端口时随机选，都可以使用
+pub fn fits_port(
+    selected: &SelectedNode,
+    csv_port: u16,
+    uri_port: u16,
+    http_ports: &[u16; 7],  // 非TLS 模式下，http的端口
+    https_ports: &[u16; 6], // TLS 模式下，https的端口
+) -> bool {
+    // 不能使用的端口
+    let reverse_ports: &[u16] = match selected.is_tls() {
+        false => https_ports,
+        true => http_ports,
+    };
+    let any = selected.node.port_mode.as_deref() == Some("any");
+    match (uri_port == 0, csv_port == 0) {
+        (true, false) => any || !reverse_ports.contains(&csv_port), // 使用csv端口
+        (false, true) => any || !reverse_ports.contains(&uri_port), // 使用uri端口
+        _ => true, // 都没有就随机选；都有就使用uri端口，不管端口是否能使用
+    }
+}
+
+// 为某个IP/域名从可以使用的节点中挑选一个并确定端口，只在端口跟tls模式匹配的节点中挑选；返回的别名跟之前的节点不重复
+#[allow(clippy::too_many_arguments)]
+pub fn resolve_node(
+    nodes: &[SelectedNode], // usable_nodes返回的节点
+    csv_tag: &str,
+    csv_addr: &str,
+    csv_port: u16,
+    uri_port: u16,
+    http_ports: &[u16; 7],  // 非TLS 模式下，http的端口
+    https_ports: &[u16; 6], // TLS 模式下，https的端口
+    picker: &mut NodePicker,
+) -> Option<ResolvedNode> {
+    let fitting: Vec<&SelectedNode> = nodes
+        .iter()
+        .filter(|selected| fits_port(selected, csv_port, uri_port, http_ports, https_ports))
         .collect();
-    all_nodes
+    if fitting.is_empty() {
+        return None;
+    }
+    let prxy = fitting[picker.pick(&fitting, csv_addr)];
+    let toml_tag: &str = &prxy.node.remarks_prefix;
+    let tls = prxy.is_tls();
+
+    // 随机的端口总是跟节点的tls模式一致；注意：不会检查配置文件的端口是否合法
+    let ports: &[u16] = match tls {
+        false => http_ports,
+        true => https_ports,
+    };
+    let ports = prxy.node.random_ports.as_deref().unwrap_or(ports);
+    let port = match (uri_port == 0, csv_port == 0) {
+        (true, true) => *ports.choose(&mut picker.rng).unwrap(), // uri端口与csv端口都没有，就随机选一个端口
+        (true, false) => csv_port, // csv端口有，就使用csv端口
+        (false, _) => uri_port,    // uri端口有，就使用uri端口
+    };
+
+    // 节点的别名
+    let remarks = match (csv_tag.trim().is_empty(), toml_tag.is_empty()) {
+        (true, true) => format!("{}:{}", csv_addr, port), // cvs_tag与toml_tag都没有
+        (false, true) => format!("{}|{}:{}", csv_tag, csv_addr, port), // 仅有csv_tag
+        (true, false) => format!("{}|{}:{}", toml_tag, csv_addr, port), // 仅有toml_tag
+        (false, false) => format!("{}{}|{}:{}", toml_tag, csv_tag, csv_addr, port), // 既有csv_tag，也有toml_tag
+    };
+
+    Some(ResolvedNode {
+        node_type: prxy.node_type.clone(),
+        remarks: picker.unique_name(remarks),
+        server: csv_addr.to_string(),
+        port,
+        tls,
+        node: prxy.node.clone(),
+    })
 }
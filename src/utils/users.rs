@@ -0,0 +1,262 @@
+use crate::utils::toml::{Node, Proxy, User};
+use toml::value::{Datetime, Offset};
+
+const NODE_TYPES: [&str; 4] = ["vless", "trojan", "ss", "vmess"];
+
+/// 用户可以使用的节点：某个类型的全部节点、某个类型的第N个节点、remarks_prefix为某个值的节点
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeRule {
+    All,
+    Type(String),
+    Index(String, usize),
+    Tag(String),
+}
+
+impl NodeRule {
+    /// 解析规则，如："*"、"vless"、"trojan:1"、"tag:①"
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if value == "*" {
+            return Ok(NodeRule::All);
+        }
+        if let Some(tag) = value.strip_prefix("tag:") {
+            return Ok(NodeRule::Tag(tag.to_string()));
+        }
+        let (node_type, index) = match value.split_once(':') {
+            Some((node_type, index)) => (node_type.trim().to_lowercase(), Some(index.trim())),
+            None => (value.to_lowercase(), None),
+        };
+        if !NODE_TYPES.contains(&node_type.as_str()) {
+            return Err(format!(
+                "`{}` 无效，节点类型只能是vless、trojan、ss、vmess",
+                value
+            ));
+        }
+        match index {
+            None => Ok(NodeRule::Type(node_type)),
+            Some(index) => match index.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(NodeRule::Index(node_type, n)),
+                _ => Err(format!("`{}` 无效，序号只能是大于0的整数", value)),
+            },
+        }
+    }
+
+    fn matches(&self, node_type: &str, index: usize, node: &Node) -> bool {
+        match self {
+            NodeRule::All => true,
+            NodeRule::Type(t) => t == node_type,
+            NodeRule::Index(t, n) => t == node_type && *n == index,
+            NodeRule::Tag(tag) => &node.remarks_prefix == tag,
+        }
+    }
+}
+
+/// 解析用户的所有规则，没有规则时可以使用全部节点
+pub fn parse_rules(user: &User) -> Result<Vec<NodeRule>, String> {
+    match user.nodes.is_empty() {
+        true => Ok(vec![NodeRule::All]),
+        false => user
+            .nodes
+            .iter()
+            .map(|rule| NodeRule::parse(rule))
+            .collect(),
+    }
+}
+
+/// 用户可以使用的节点配置，后续只从这些节点中挑选
+pub fn entitled_proxies(proxies: &Proxy, rules: &[NodeRule]) -> Proxy {
+    proxies.retain_nodes(|node_type, index, node| {
+        rules
+            .iter()
+            .any(|rule| rule.matches(node_type, index, node))
+    })
+}
+
+/// 根据token查找用户，比较时不会因为token前面的字符相同而提前返回
+pub fn find_user<'a>(users: &'a [User], token: &str) -> Option<&'a User> {
+    users.iter().find(|user| {
+        !user.token.is_empty() && constant_time_eq(user.token.as_bytes(), token.as_bytes())
+    })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// 将用户默认的URI参数加到请求的参数前面，请求中已经有的参数不使用默认值
+pub fn with_default_params(user: &User, params: Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut merged: Vec<(String, String)> = user
+        .params
+        .iter()
+        .filter(|(key, _)| !params.iter().any(|(k, _)| k.eq_ignore_ascii_case(key)))
+        .map(|(key, value)| {
+            let value = match value {
+                toml::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            (key.clone(), value)
+        })
+        .collect();
+    merged.extend(params);
+    merged
+}
+
+/// 用户的到期时间(Unix时间戳，秒)，只有日期时，到当天(UTC)结束为止
+pub fn expire_timestamp(user: &User) -> Result<Option<u64>, String> {
+    let datetime = match &user.expire {
+        None => return Ok(None),
+        Some(toml::Value::Integer(n)) if *n >= 0 => return Ok(Some(*n as u64)),
+        Some(toml::Value::Datetime(datetime)) => *datetime,
+        Some(toml::Value::String(s)) => s
+            .trim()
+            .parse::<Datetime>()
+            .map_err(|_| format!("`{}` 不是有效的日期，如：2026-12-31", s))?,
+        Some(other) => {
+            return Err(format!(
+                "`{}` 无效，只能是日期(如：2026-12-31)或Unix时间戳",
+                other
+            ))
+        }
+    };
+    let date = datetime.date.ok_or(format!("`{}` 缺少日期", datetime))?;
+    let days = days_from_civil(date.year as i64, date.month as i64, date.day as i64);
+    let seconds = match datetime.time {
+        Some(time) => {
+            let offset = match datetime.offset {
+                Some(Offset::Custom { minutes }) => minutes as i64 * 60,
+                _ => 0, // 没有时区的当作UTC
+            };
+            days * 86400 + time.hour as i64 * 3600 + time.minute as i64 * 60 + time.second as i64
+                - offset
+        }
+        None => (days + 1) * 86400,
+    };
+    Ok(Some(seconds.max(0) as u64))
+}
+
+/// 配置文件中到期时间的原始写法，用于提示信息
+pub fn expire_text(user: &User) -> String {
+    match &user.expire {
+        Some(toml::Value::Datetime(datetime)) => datetime.to_string(),
+        Some(toml::Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+        None => "".to_string(),
+    }
+}
+
+// 公历日期转换为从1970-01-01开始的天数
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(toml: &str) -> User {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn parses_node_rules() {
+        assert_eq!(NodeRule::parse(" * "), Ok(NodeRule::All));
+        assert_eq!(
+            NodeRule::parse("VLESS"),
+            Ok(NodeRule::Type("vless".to_string()))
+        );
+        assert_eq!(
+            NodeRule::parse("trojan: 2"),
+            Ok(NodeRule::Index("trojan".to_string(), 2))
+        );
+        assert_eq!(NodeRule::parse("tag:①"), Ok(NodeRule::Tag("①".to_string())));
+        assert!(NodeRule::parse("http").is_err());
+        assert!(NodeRule::parse("vless:0").is_err());
+        assert!(NodeRule::parse("vless:x").is_err());
+        // 没有规则时可以使用全部节点
+        assert_eq!(parse_rules(&user("token = \"a\"")), Ok(vec![NodeRule::All]));
+    }
+
+    #[test]
+    fn entitles_matching_nodes() {
+        let proxies: Proxy = toml::from_str(
+            r#"
+            [[vless]]
+            remarks_prefix = "a"
+            host = "a.com"
+            path = "/"
+            [[vless]]
+            remarks_prefix = "b"
+            host = "b.com"
+            path = "/"
+            [[trojan]]
+            remarks_prefix = "c"
+            host = "c.com"
+            path = "/"
+            "#,
+        )
+        .unwrap();
+        let tags = |rules: &[NodeRule]| -> Vec<String> {
+            entitled_proxies(&proxies, rules)
+                .node_groups()
+                .iter()
+                .flat_map(|(_, nodes)| nodes.iter())
+                .map(|node| node.remarks_prefix.clone())
+                .collect()
+        };
+        assert_eq!(tags(&[NodeRule::All]), ["a", "b", "c"]);
+        assert_eq!(tags(&[NodeRule::Type("vless".to_string())]), ["a", "b"]);
+        assert_eq!(
+            tags(&[
+                NodeRule::Index("vless".to_string(), 2),
+                NodeRule::Tag("c".to_string())
+            ]),
+            ["b", "c"]
+        );
+    }
+
+    #[test]
+    fn finds_user_by_exact_token() {
+        let users = vec![
+            user("name = \"empty\"\ntoken = \"\""),
+            user("token = \"abc\""),
+        ];
+        assert!(find_user(&users, "abc").is_some());
+        assert!(find_user(&users, "ab").is_none());
+        assert!(find_user(&users, "abcd").is_none());
+        // token为空的用户不能匹配
+        assert!(find_user(&users, "").is_none());
+    }
+
+    #[test]
+    fn days_from_civil_handles_leap_years() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 2, 29), 11016);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19782);
+        // 2100年不是闰年
+        assert_eq!(days_from_civil(2100, 2, 28), 47540);
+        assert_eq!(days_from_civil(2100, 3, 1), 47541);
+    }
+
+    #[test]
+    fn expire_timestamp_formats() {
+        let expire =
+            |value: &str| expire_timestamp(&user(&format!("token = \"a\"\nexpire = {}", value)));
+        // 只有日期时，到当天(UTC)结束
+        assert_eq!(expire("2026-12-31"), Ok(Some(1798761600)));
+        assert_eq!(expire("\"2026-12-31\""), Ok(Some(1798761600)));
+        assert_eq!(expire("2026-12-31T20:00:00+08:00"), Ok(Some(1798718400)));
+        assert_eq!(expire("1798718400"), Ok(Some(1798718400)));
+        assert_eq!(expire_timestamp(&user("token = \"a\"")), Ok(None));
+        assert!(expire("-1").is_err());
+        assert!(expire("\"tomorrow\"").is_err());
+        assert!(expire("12:00:00").is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::utils::{
+    dns::{parse_hosts, parse_server},
+    toml::{Config, Node},
+    users::{entitled_proxies, expire_timestamp, parse_rules},
+};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    // 匹配UUID（8-4-4-4-12位十六进制）
+    static ref UUID_REGEX: Regex = Regex::new(
+        r"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$"
+    ).unwrap();
+}
+
+// 配置文件中的一个问题，location是问题在toml中的位置，如：proxies.vless[0].uuid
+#[derive(Debug, Clone)]
+pub struct ConfigProblem {
+    pub location: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+/// 读取并解析toml配置文件，解析失败时返回的错误信息含有行号和列号
+pub fn load_config(path: &str) -> Result<Config, String> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("读取 `{}` 失败：{}", path, e))?;
+    toml::from_str(&content).map_err(|e| format!("解析 `{}` 失败：{}", path, e))
+}
+
+/// 检查toml配置中的所有节点，返回发现的全部问题
+pub fn validate_config(
+    config: &Config,
+    http_ports: &[u16; 7],  // 非TLS 模式下，http的端口
+    https_ports: &[u16; 6], // TLS 模式下，https的端口
+) -> Vec<ConfigProblem> {
+    let mut problems: Vec<ConfigProblem> = Vec::new();
+
+    let groups = config.proxies.node_groups();
+    if groups.iter().all(|(_, nodes)| nodes.is_empty()) {
+        problems.push(ConfigProblem {
+            location: "proxies".to_string(),
+            message: "没有配置任何节点".to_string(),
+        });
+    }
+
+    // DNS服务器和[dns.hosts]中的IP地址
+    let server = config.dns.server.trim().to_lowercase();
+    if !["system", "stub", ""].contains(&server.as_str()) {
+        if let Err(message) = parse_server(&config.dns.server) {
+            problems.push(ConfigProblem {
+                location: "dns.server".to_string(),
+                message,
+            });
+        }
+    }
+    if let Err(message) = parse_hosts(&config.dns.hosts) {
+        problems.push(ConfigProblem {
+            location: "dns.hosts".to_string(),
+            message,
+        });
+    }
+
+    // 限制了请求速度时，至少可以连续请求一次
+    let limits = &config.limits;
+    for (per_minute, burst, field) in [
+        (limits.ip_per_minute, limits.ip_burst, "ip_burst"),
+        (limits.token_per_minute, limits.token_burst, "token_burst"),
+    ] {
+        if per_minute > 0 && burst == 0 {
+            problems.push(ConfigProblem {
+                location: format!("limits.{}", field),
+                message: "限制了请求速度时，至少为1".to_string(),
+            });
+        }
+    }
+
+    // 用户的token不能为空、不能重复，每个用户至少可以使用一个节点
+    for (i, user) in config.users.iter().enumerate() {
+        let location = format!("users[{}]", i);
+        let mut push = |field: &str, message: String| {
+            problems.push(ConfigProblem {
+                location: format!("{}.{}", location, field),
+                message,
+            });
+        };
+        if user.token.trim().is_empty() {
+            push("token", "token不能为空".to_string());
+        } else if config.users[..i].iter().any(|u| u.token == user.token) {
+            push("token", "token跟前面的用户重复".to_string());
+        }
+        match parse_rules(user) {
+            Ok(rules) => {
+                let proxies = entitled_proxies(&config.proxies, &rules);
+                let groups = proxies.node_groups();
+                if groups.iter().all(|(_, nodes)| nodes.is_empty()) {
+                    push("nodes", "没有匹配到任何节点".to_string());
+                }
+            }
+            Err(message) => push("nodes", message),
+        }
+        if let Err(message) = expire_timestamp(user) {
+            push("expire", message);
+        }
+        if user.max_requests_per_day == Some(0) {
+            push("max_requests_per_day", "每天的请求次数至少为1".to_string());
+        }
+    }
+
+    for (node_type, nodes) in groups {
+        for (i, node) in nodes.iter().enumerate() {
+            let location = format!("proxies.{}[{}]", node_type, i);
+            validate_node(node_type, node, &location, http_ports, https_ports, &mut problems);
+        }
+    }
+
+    problems
+}
+
+fn validate_node(
+    node_type: &str,
+    node: &Node,
+    location: &str,
+    http_ports: &[u16; 7],
+    https_ports: &[u16; 6],
+    problems: &mut Vec<ConfigProblem>,
+) {
+    let mut push = |field: &str, message: String| {
+        problems.push(ConfigProblem {
+            location: format!("{}.{}", location, field),
+            message,
+        });
+    };
+
+    match node_type {
+        "vless" | "vmess" => match node.uuid.as_deref() {
+            Some(uuid) if UUID_REGEX.is_match(uuid) => {}
+            Some(uuid) => push("uuid", format!("`{}` 不是有效的UUID", uuid)),
+            None => push("uuid", "缺少uuid".to_string()),
+        },
+        "trojan" if node.password.as_deref().unwrap_or("").trim().is_empty() => {
+            push("password", "trojan的密码不能为空".to_string())
+        }
+        _ => {}
+    }
+
+    if node.host.trim().is_empty() {
+        push("host", "host不能为空".to_string());
+    }
+    if !node.path.starts_with('/') {
+        push("path", format!("`{}` 必须以 / 开头", node.path));
+    }
+
+    let tls = node.is_tls(node_type);
+    // ss使用host作为sni，其它协议开启tls时，需要server_name
+    if tls && node_type != "ss" && node.server_name.as_deref().unwrap_or("").trim().is_empty() {
+        push("server_name", "开启tls时，需要设置server_name".to_string());
+    }
+
+    // 端口的集合由tls决定，port_mode只能决定是否检查csv、uri中的端口
+    let port_mode = node.port_mode.as_deref().unwrap_or("auto");
+    if !["auto", "any"].contains(&port_mode) {
+        push(
+            "port_mode",
+            format!(
+                "`{}` 无效，可选：auto、any（使用http/https端口请设置tls字段）",
+                port_mode
+            ),
+        );
+    }
+
+    if let Some(ports) = &node.random_ports {
+        if ports.is_empty() {
+            push("random_ports", "不能为空数组".to_string());
+        }
+        // 跟节点tls模式不匹配的端口，port_mode为any时也要跟tls模式一致
+        let reverse_ports: &[u16] = match tls {
+            false => https_ports,
+            true => http_ports,
+        };
+        for (i, port) in ports.iter().enumerate() {
+            if *port == 0 {
+                push(
+                    &format!("random_ports[{}]", i),
+                    "端口必须在1..=65535之间".to_string(),
+                );
+            } else if reverse_ports.contains(port) {
+                let mode = if tls { "tls" } else { "非tls" };
+                push(
+                    &format!("random_ports[{}]", i),
+                    format!("端口{}跟节点的{}模式不匹配", port, mode),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HTTP_PORTS: [u16; 7] = [80, 8080, 8880, 2052, 2082, 2086, 2095];
+    const HTTPS_PORTS: [u16; 6] = [443, 2053, 2083, 2087, 2096, 8443];
+
+    fn locations(toml: &str) -> Vec<String> {
+        let config: Config = toml::from_str(toml).unwrap();
+        validate_config(&config, &HTTP_PORTS, &HTTPS_PORTS)
+            .into_iter()
+            .map(|problem| problem.location)
+            .collect()
+    }
+
+    const VALID_VLESS: &str = r#"
+        [[proxies.vless]]
+        remarks_prefix = ""
+        uuid = "0195bf7c-f8c6-7add-aa36-a2145cc38ebb"
+        host = "a.workers.dev"
+        path = "/"
+    "#;
+
+    #[test]
+    fn valid_config_has_no_problems() {
+        assert!(locations(VALID_VLESS).is_empty());
+    }
+
+    #[test]
+    fn reports_empty_proxies() {
+        assert_eq!(locations("[proxies]"), ["proxies"]);
+    }
+
+    #[test]
+    fn reports_node_field_locations() {
+        let toml = format!(
+            r#"{}
+            [[proxies.vless]]
+            remarks_prefix = ""
+            uuid = "not-a-uuid"
+            host = " "
+            path = "ws"
+            [[proxies.trojan]]
+            remarks_prefix = ""
+            host = "b.com"
+            path = "/"
+            tls = true
+            port_mode = "https"
+            random_ports = [443, 0, 80]
+            "#,
+            VALID_VLESS
+        );
+        assert_eq!(
+            locations(&toml),
+            [
+                "proxies.vless[1].uuid",
+                "proxies.vless[1].host",
+                "proxies.vless[1].path",
+                "proxies.vless[1].server_name",
+                "proxies.trojan[0].password",
+                "proxies.trojan[0].server_name",
+                "proxies.trojan[0].port_mode",
+                "proxies.trojan[0].random_ports[1]",
+                "proxies.trojan[0].random_ports[2]",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_users_limits_and_dns_locations() {
+        let toml = format!(
+            r#"
+            [dns]
+            server = "not an address"
+            [dns.hosts]
+            "a.com" = ["not an ip"]
+            [limits]
+            ip_per_minute = 60
+            ip_burst = 0
+            [[users]]
+            token = ""
+            nodes = ["http"]
+            [[users]]
+            token = "abc"
+            nodes = ["trojan"]
+            expire = "tomorrow"
+            max_requests_per_day = 0
+            [[users]]
+            token = "abc"
+            {}
+            "#,
+            VALID_VLESS
+        );
+        assert_eq!(
+            locations(&toml),
+            [
+                "dns.server",
+                "dns.hosts",
+                "limits.ip_burst",
+                "users[0].token",
+                "users[0].nodes",
+                "users[1].nodes",
+                "users[1].expire",
+                "users[1].max_requests_per_day",
+                "users[2].token",
+            ]
+        );
+    }
+}
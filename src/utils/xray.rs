@@ -0,0 +1,158 @@
+use crate::utils::{
+    strategy::NodePicker,
+    toml::{resolve_node, SelectedNode},
+};
+use serde_json::{json, Value as JsonValue};
+
+// xray-core不支持ss的v2ray-plugin插件
+pub fn is_supported(selected: &SelectedNode) -> bool {
+    ["vless", "vmess", "trojan"].contains(&selected.node_type.as_str())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn build_xray_json(
+    nodes: &[SelectedNode], // usable_nodes返回的节点（已经剔除了客户端不支持的节点）
+    csv_tag: String,
+    csv_addr: String,
+    csv_port: u16,
+    uri_port: u16,
+    fingerprint: String,
+    http_ports: &[u16; 7],  // 非TLS 模式下，http的端口
+    https_ports: &[u16; 6], // TLS 模式下，https的端口
+    picker: &mut NodePicker,
+) -> (String, JsonValue) {
+    let Some(resolved) = resolve_node(
+        nodes,
+        &csv_tag,
+        &csv_addr,
+        csv_port,
+        uri_port,
+        http_ports,
+        https_ports,
+        picker,
+    ) else {
+        return ("".to_string(), JsonValue::Null);
+    };
+
+    let node = &resolved.node;
+    let settings = match resolved.node_type.as_str() {
+        "vless" => json!({
+            "vnext": [{
+                "address": resolved.server,
+                "port": resolved.port,
+                "users": [{"id": node.uuid.clone().unwrap_or_default(), "encryption": "none"}]
+            }]
+        }),
+        "vmess" => json!({
+            "vnext": [{
+                "address": resolved.server,
+                "port": resolved.port,
+                "users": [{"id": node.uuid.clone().unwrap_or_default(), "alterId": 0, "security": "zero"}]
+            }]
+        }),
+        "trojan" => json!({
+            "servers": [{
+                "address": resolved.server,
+                "port": resolved.port,
+                "password": node.password.clone().unwrap_or_default()
+            }]
+        }),
+        _ => return ("".to_string(), JsonValue::Null),
+    };
+
+    let mut stream_settings = json!({
+        "network": "ws",
+        "security": "none",
+        "wsSettings": {
+            "path": node.path,
+            "headers": {"Host": node.host}
+        }
+    });
+    if resolved.tls {
+        stream_settings["security"] = json!("tls");
+        stream_settings["tlsSettings"] = json!({
+            "serverName": node.server_name.clone().unwrap_or(node.host.clone()),
+            "allowInsecure": true,
+            "fingerprint": fingerprint
+        });
+    }
+
+    let outbound = json!({
+        "tag": resolved.remarks,
+        "protocol": resolved.node_type,
+        "settings": settings,
+        "streamSettings": stream_settings
+    });
+
+    (resolved.remarks, outbound)
+}
+
+/// 没有模板时使用的默认配置：socks/http入站，所有节点负载均衡
+pub fn default_xray_template() -> JsonValue {
+    json!({
+        "log": {"loglevel": "warning"},
+        "inbounds": [
+            {"tag": "socks-in", "listen": "127.0.0.1", "port": 10808, "protocol": "socks", "settings": {"udp": true}},
+            {"tag": "http-in", "listen": "127.0.0.1", "port": 10809, "protocol": "http"}
+        ],
+        "outbounds": [
+            {"tag": "direct", "protocol": "freedom"},
+            {"tag": "block", "protocol": "blackhole"}
+        ],
+        "routing": {
+            "domainStrategy": "AsIs",
+            "balancers": [{"tag": "proxy", "selector": ["{all}"]}],
+            "rules": [{"type": "field", "network": "tcp,udp", "balancerTag": "proxy"}]
+        }
+    })
+}
+
+/// 将节点插入模板outbounds的最前面，并将balancers的selector（以及observatory的subjectSelector）中的{all}替换为节点的tag
+pub fn add_xray_template(template: JsonValue, outbounds_vec: Vec<(String, String)>) -> String {
+    let mut xray_template = template;
+    let inside_outbounds_data: Vec<(String, JsonValue)> = outbounds_vec
+        .iter()
+        .filter(|(k, _)| !k.is_empty())
+        .map(|(k, v)| {
+            (
+                k.clone(),
+                serde_json::from_str(v).unwrap_or(JsonValue::Null),
+            )
+        })
+        .collect();
+
+    if let Some(outside_outbounds) = xray_template["outbounds"].as_array_mut() {
+        inside_outbounds_data
+            .iter()
+            .enumerate()
+            .for_each(|(i, (_, value))| outside_outbounds.insert(i, value.clone()));
+    }
+
+    // 查找并删除目标值 "{all}"、并将节点的tag合并进来
+    let expand_all = |selector: &mut Vec<JsonValue>| {
+        if let Some(pos) = selector.iter().position(|x| x.as_str() == Some("{all}")) {
+            selector.remove(pos);
+            selector.extend(
+                inside_outbounds_data
+                    .iter()
+                    .map(|(k, _)| JsonValue::String(k.to_string())),
+            );
+        }
+    };
+
+    if let Some(balancers) = xray_template["routing"]["balancers"].as_array_mut() {
+        balancers.iter_mut().for_each(|balancer| {
+            if let Some(selector) = balancer
+                .get_mut("selector")
+                .and_then(JsonValue::as_array_mut)
+            {
+                expand_all(selector);
+            }
+        });
+    }
+    if let Some(selector) = xray_template["observatory"]["subjectSelector"].as_array_mut() {
+        expand_all(selector);
+    }
+
+    serde_json::to_string_pretty(&xray_template).unwrap_or_default()
+}